@@ -0,0 +1,127 @@
+//! Either transport the UI can play over: PeerJS,
+//! or a self-hosted relay server.
+
+use robots::net::{Message, Metadata};
+use robots::relay::{RelayConnection, RelayPeer};
+use robots::transport::{Connection, Transport};
+use wasm_bindgen::JsValue;
+use wasm_bindgen::prelude::Closure;
+use web_sys::console;
+
+use crate::peer;
+
+#[derive(Clone)]
+pub enum AnyTransport {
+    PeerJs(peer::Peer),
+    Relay(RelayPeer),
+}
+
+#[derive(Clone)]
+pub enum AnyConnection {
+    PeerJs(peer::DataConnection),
+    Relay(RelayConnection),
+}
+
+impl AnyTransport {
+    /// Called when this peer loses the network.
+    pub fn on_disconnect(&self, f: impl Fn() + 'static) {
+        match self {
+            AnyTransport::PeerJs(peer) => {
+                peer.on("error", &Closure::<dyn Fn(JsValue)>::new(move |err| {
+                    console::error_1(&err);
+                }).into_js_value());
+                peer.on("close", &Closure::<dyn Fn()>::new(f).into_js_value());
+            },
+            AnyTransport::Relay(relay) => relay.on_disconnect(f),
+        }
+    }
+}
+
+impl Connection for AnyConnection {
+    fn peer(&self) -> String {
+        match self {
+            AnyConnection::PeerJs(conn) => Connection::peer(conn),
+            AnyConnection::Relay(conn) => conn.peer(),
+        }
+    }
+
+    fn metadata(&self) -> Metadata {
+        match self {
+            AnyConnection::PeerJs(conn) => Connection::metadata(conn),
+            AnyConnection::Relay(conn) => conn.metadata(),
+        }
+    }
+
+    fn send(&self, message: &Message) {
+        match self {
+            AnyConnection::PeerJs(conn) => Connection::send(conn, message),
+            AnyConnection::Relay(conn) => conn.send(message),
+        }
+    }
+
+    fn close(&self) {
+        match self {
+            AnyConnection::PeerJs(conn) => Connection::close(conn),
+            AnyConnection::Relay(conn) => conn.close(),
+        }
+    }
+
+    fn on_open(&self, f: impl Fn() + 'static) {
+        match self {
+            AnyConnection::PeerJs(conn) => conn.on_open(f),
+            AnyConnection::Relay(conn) => conn.on_open(f),
+        }
+    }
+
+    fn on_data(&self, f: impl Fn(Message) + 'static) {
+        match self {
+            AnyConnection::PeerJs(conn) => conn.on_data(f),
+            AnyConnection::Relay(conn) => conn.on_data(f),
+        }
+    }
+
+    fn on_close(&self, f: impl Fn() + 'static) {
+        match self {
+            AnyConnection::PeerJs(conn) => conn.on_close(f),
+            AnyConnection::Relay(conn) => conn.on_close(f),
+        }
+    }
+}
+
+impl Transport for AnyTransport {
+    type Connection = AnyConnection;
+
+    fn id(&self) -> String {
+        match self {
+            AnyTransport::PeerJs(peer) => Transport::id(peer),
+            AnyTransport::Relay(relay) => relay.id(),
+        }
+    }
+
+    fn connect(&self, id: &str, metadata: &Metadata) -> AnyConnection {
+        match self {
+            AnyTransport::PeerJs(peer) => {
+                let conn = Transport::connect(peer, id, metadata);
+                conn.on("error", &Closure::<dyn Fn(JsValue)>::new(move |err| {
+                    console::error_1(&err);
+                }).into_js_value());
+                AnyConnection::PeerJs(conn)
+            },
+            AnyTransport::Relay(relay) => AnyConnection::Relay(relay.connect(id, metadata)),
+        }
+    }
+
+    fn on_connection(&self, f: impl Fn(AnyConnection) + 'static) {
+        match self {
+            AnyTransport::PeerJs(peer) => peer.on_connection(move |conn| f(AnyConnection::PeerJs(conn))),
+            AnyTransport::Relay(relay) => relay.on_connection(move |conn| f(AnyConnection::Relay(conn))),
+        }
+    }
+
+    fn destroy(&self) {
+        match self {
+            AnyTransport::PeerJs(peer) => Transport::destroy(peer),
+            AnyTransport::Relay(relay) => relay.destroy(),
+        }
+    }
+}
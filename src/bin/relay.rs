@@ -0,0 +1,16 @@
+//! Relays games between browsers over WebSockets, for
+//! networks where the PeerJS servers can't be reached.
+//!
+//! Usage: `relay [ADDRESS]`, listening on `0.0.0.0:9000` by default.
+
+use std::net::TcpListener;
+
+use robots::relay;
+
+fn main() -> std::io::Result<()> {
+    let address = std::env::args().nth(1).unwrap_or_else(|| "0.0.0.0:9000".to_string());
+    let listener = TcpListener::bind(&address)?;
+    println!("relaying on ws://{}", listener.local_addr()?);
+    relay::server::serve(listener);
+    Ok(())
+}
@@ -0,0 +1,91 @@
+//! Hosts a room with no player of its own, so it keeps running
+//! whoever leaves. It joins a relay like a browser host would,
+//! and browsers join it with the room code it prints.
+//!
+//! Usage: `server [RELAY] [ROOM]`, using the relay at
+//! `ws://127.0.0.1:9000` and a random room code by default.
+
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use robots::board::{Board, GenerateOptions};
+use robots::game::{Game, Phase};
+use robots::host::Host;
+use robots::rand::Rng;
+use robots::relay::client::Client;
+use robots::relay::RelayPeer;
+
+/// How often the game clock advances.
+const TICK: Duration = Duration::from_millis(50);
+
+/// How long each bidder has to show their moves, in milliseconds.
+/// There's no host at the table to skip someone who walked away.
+const EVAL_DURATION: u64 = 120_000;
+
+/// How long to wait before reconnecting to the relay after
+/// losing it, doubling after each failed try up to `MAX_BACKOFF`.
+const BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+fn main() -> io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let url = args.next().unwrap_or_else(|| "ws://127.0.0.1:9000".to_string());
+    let mut rng = Rng::from_entropy();
+    let room = args.next().unwrap_or_else(|| format!("{:x}", rng.uniform(0, i32::MAX as usize)));
+
+    let options = GenerateOptions::default();
+    let board = Board::generate(&options, &mut rng)
+        .map_err(io::Error::other)?;
+    let mut game = Game::new(board, rng);
+    game.eval_duration = Some(EVAL_DURATION);
+    game.new_boards = Some(options);
+
+    // browsers expect the host, and its relay room, under this ID
+    let id = format!("ripoff-robots-{}", room);
+    // a relay that can't be reached at first is likely the wrong one
+    let mut client = Client::connect(&url, &id, &id)?;
+    let start = Instant::now();
+    let now = move || start.elapsed().as_millis() as u64;
+    loop {
+        let host = Host::new(client.peer().clone(), game, now, |_| {});
+        println!("hosting room {} on {}", room, url);
+        let err = run(&client, &host);
+        eprintln!("lost the relay: {}", err);
+
+        // players keep their scores for a while, to reclaim
+        // when they find the room again
+        game = host.with_game(|game| game.clone());
+        drop(host);
+        client = reconnect(&url, &id);
+    }
+}
+
+/// Runs the game until the socket to the relay fails.
+fn run(client: &Client, host: &Host<RelayPeer>) -> io::Error {
+    loop {
+        if let Err(err) = client.poll(TICK) {
+            return err;
+        }
+        host.tick();
+        host.update(|game, _| {
+            if game.phase == Phase::Idle && !game.players.is_empty() {
+                return game.start_round();
+            }
+            Vec::new()
+        });
+    }
+}
+
+/// Connects to the relay again, however many tries it takes.
+fn reconnect(url: &str, id: &str) -> Client {
+    let mut backoff = BACKOFF;
+    loop {
+        thread::sleep(backoff);
+        match Client::connect(url, id, id) {
+            Ok(client) => return client,
+            Err(err) => eprintln!("reconnecting to {}: {}", url, err),
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
@@ -1,8 +1,12 @@
 use std::collections::HashSet;
+use std::fmt;
+use std::sync::OnceLock;
 
 use serde::{Serialize, Deserialize};
 
-use crate::rand;
+use crate::engine::MoveTable;
+use crate::quadrant;
+use crate::rand::Rng;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -13,6 +17,8 @@ pub enum Direction {
     Right
 }
 impl Direction {
+    pub const ALL: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
     pub fn id(self) -> &'static str {
         match self {
             Direction::Up => "up",
@@ -24,7 +30,96 @@ impl Direction {
 }
 pub type RobotPositions = [usize; 5];
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The symbol printed on a target token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Symbol {
+    Moon,
+    Gear,
+    Planet,
+    Star,
+    Vortex
+}
+impl Symbol {
+    pub const COLORED: [Symbol; 4] = [Symbol::Moon, Symbol::Gear, Symbol::Planet, Symbol::Star];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Symbol::Moon => "moon",
+            Symbol::Gear => "gear",
+            Symbol::Planet => "planet",
+            Symbol::Star => "star",
+            Symbol::Vortex => "vortex"
+        }
+    }
+}
+
+/// A target token placed on the board.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Target {
+    pub tile: usize,
+
+    /// The robot that has to reach this target,
+    /// or `None` if any robot may (the vortex).
+    pub robot: Option<usize>,
+
+    pub symbol: Symbol
+}
+impl Target {
+    /// Returns whether the given robot scores
+    /// by reaching this target.
+    pub fn accepts(&self, robot: usize) -> bool {
+        self.robot.is_none_or(|r| r == robot)
+    }
+}
+
+/// Which way a diagonal barrier leans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    /// Runs from the bottom left to the top right, like `/`.
+    Slash,
+    /// Runs from the top left to the bottom right, like `\`.
+    Backslash
+}
+impl Orientation {
+    pub fn id(self) -> &'static str {
+        match self {
+            Orientation::Slash => "slash",
+            Orientation::Backslash => "backslash"
+        }
+    }
+
+    /// Returns the direction a robot moving in
+    /// `direction` leaves the barrier in.
+    pub fn deflect(self, direction: Direction) -> Direction {
+        match (self, direction) {
+            (Orientation::Slash, Direction::Right) => Direction::Up,
+            (Orientation::Slash, Direction::Up) => Direction::Right,
+            (Orientation::Slash, Direction::Left) => Direction::Down,
+            (Orientation::Slash, Direction::Down) => Direction::Left,
+            (Orientation::Backslash, Direction::Right) => Direction::Down,
+            (Orientation::Backslash, Direction::Down) => Direction::Right,
+            (Orientation::Backslash, Direction::Left) => Direction::Up,
+            (Orientation::Backslash, Direction::Up) => Direction::Left
+        }
+    }
+}
+
+/// A colored diagonal barrier. Robots of other colors are
+/// deflected by 90 degrees when they enter its tile, while
+/// the robot of the same color passes straight through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Diagonal {
+    pub tile: usize,
+    pub robot: usize,
+    pub orientation: Orientation
+}
+
+/// Boards received from other peers are checked with
+/// `BoardData` before anything relies on their size.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(try_from = "BoardData")]
 pub struct Board {
     pub width: usize,
 
@@ -38,8 +133,206 @@ pub struct Board {
     /// verticall_walls[i, j] is true.
     pub vertical_walls: Vec<bool>,
 
+    /// Whether the center tiles are blocked off.
+    pub center: bool,
+
     // Initial position of the robots
-    pub initial_positions: RobotPositions
+    pub initial_positions: RobotPositions,
+
+    /// Target tokens, each placed in the corner of an L-wall.
+    pub targets: Vec<Target>,
+
+    /// Diagonal barriers, at most one per tile.
+    pub diagonals: Vec<Diagonal>,
+
+    /// Lazily built from the walls on the first move. `set_wall`
+    /// throws it away, but walls changed directly must not change
+    /// after that. Clones build their own.
+    #[serde(skip)]
+    pub(crate) table: OnceLock<MoveTable>
+}
+
+impl Clone for Board {
+    fn clone(&self) -> Self {
+        Board {
+            width: self.width,
+            horizontal_walls: self.horizontal_walls.clone(),
+            vertical_walls: self.vertical_walls.clone(),
+            center: self.center,
+            initial_positions: self.initial_positions,
+            targets: self.targets.clone(),
+            diagonals: self.diagonals.clone(),
+            table: OnceLock::new()
+        }
+    }
+}
+
+/// A board as received, before it's checked.
+#[derive(Deserialize)]
+struct BoardData {
+    width: usize,
+    horizontal_walls: Vec<bool>,
+    vertical_walls: Vec<bool>,
+    center: bool,
+    initial_positions: RobotPositions,
+    targets: Vec<Target>,
+    diagonals: Vec<Diagonal>,
+}
+
+/// Why a received board was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BoardError {
+    /// Width or height is outside `MIN_SIZE..=MAX_SIZE`.
+    InvalidSize { width: usize, height: usize },
+
+    /// There are too many or too few walls for the board's size.
+    MismatchedWalls,
+
+    /// A robot, target or diagonal is off the board.
+    OffBoard
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoardError::InvalidSize { width, height } =>
+                write!(f, "a {}x{} board is not supported; width and height must be between {} and {}",
+                    width, height, MIN_SIZE, MAX_SIZE),
+            BoardError::MismatchedWalls => write!(f, "the walls don't fit the board"),
+            BoardError::OffBoard => write!(f, "a robot, target or diagonal is off the board")
+        }
+    }
+}
+
+impl std::error::Error for BoardError {}
+
+impl TryFrom<BoardData> for Board {
+    type Error = BoardError;
+
+    fn try_from(data: BoardData) -> Result<Self, BoardError> {
+        let width = data.width;
+        let height = data.horizontal_walls.len().checked_div(width).map_or(0, |rows| rows + 1);
+        if !(MIN_SIZE..=MAX_SIZE).contains(&width) || !(MIN_SIZE..=MAX_SIZE).contains(&height) {
+            return Err(BoardError::InvalidSize { width, height });
+        }
+        if data.horizontal_walls.len() != width * (height - 1) || data.vertical_walls.len() != (width - 1) * height {
+            return Err(BoardError::MismatchedWalls);
+        }
+        let tiles = width * height;
+        if data.initial_positions.iter().any(|&tile| tile >= tiles)
+            || data.targets.iter().any(|target| target.tile >= tiles)
+            || data.diagonals.iter().any(|diagonal| diagonal.tile >= tiles) {
+            return Err(BoardError::OffBoard);
+        }
+
+        Ok(Board {
+            width,
+            horizontal_walls: data.horizontal_walls,
+            vertical_walls: data.vertical_walls,
+            center: data.center,
+            initial_positions: data.initial_positions,
+            targets: data.targets,
+            diagonals: data.diagonals,
+            table: OnceLock::new()
+        })
+    }
+}
+
+/// Where `Board::generate` puts the robots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RobotPlacement {
+    /// Random distinct tiles that aren't center,
+    /// target or diagonal tiles.
+    Random,
+
+    /// Keep the robots where they are, such as where the
+    /// previous round ended. Robots that would end up off the board
+    /// or on a center, target or diagonal tile are placed randomly.
+    Keep(RobotPositions)
+}
+
+/// How `Board::generate` lays out walls and targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Four quarter boards from the physical game.
+    /// Only for 16x16 boards.
+    Quadrants,
+
+    /// L-walls scattered at random.
+    Scattered
+}
+
+/// Options for `Board::generate`.
+#[derive(Debug, Clone)]
+pub struct GenerateOptions {
+    pub width: usize,
+    pub height: usize,
+    pub layout: Layout,
+
+    /// Whether to block off the center tiles.
+    pub center: bool,
+
+    /// Number of diagonal barriers to place.
+    pub diagonals: usize,
+
+    pub robots: RobotPlacement
+}
+
+/// Smallest supported board width or height.
+pub const MIN_SIZE: usize = 6;
+
+/// Largest supported board width or height,
+/// bounded by the move table's line bitboards.
+pub const MAX_SIZE: usize = 64;
+
+/// Why `Board::generate` rejected its options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GenerateError {
+    /// Width or height is outside `MIN_SIZE..=MAX_SIZE`.
+    InvalidSize { width: usize, height: usize },
+
+    /// The layout doesn't support boards of this size.
+    UnsupportedLayout { layout: Layout, width: usize, height: usize }
+}
+
+impl fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenerateError::InvalidSize { width, height } =>
+                write!(f, "a {}x{} board is not supported; width and height must be between {} and {}",
+                    width, height, MIN_SIZE, MAX_SIZE),
+            GenerateError::UnsupportedLayout { layout, width, height } =>
+                write!(f, "the {:?} layout does not support a {}x{} board", layout, width, height)
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+impl GenerateOptions {
+    pub fn validate(&self) -> Result<(), GenerateError> {
+        let GenerateOptions { width, height, layout, .. } = *self;
+        if !(MIN_SIZE..=MAX_SIZE).contains(&width) || !(MIN_SIZE..=MAX_SIZE).contains(&height) {
+            return Err(GenerateError::InvalidSize { width, height });
+        }
+        if layout == Layout::Quadrants && (width != 2 * quadrant::SIZE || height != 2 * quadrant::SIZE) {
+            return Err(GenerateError::UnsupportedLayout { layout, width, height });
+        }
+        Ok(())
+    }
+}
+
+impl Default for GenerateOptions {
+    fn default() -> Self {
+        GenerateOptions {
+            width: 16,
+            height: 16,
+            layout: Layout::Quadrants,
+            center: true,
+            diagonals: 0,
+            robots: RobotPlacement::Random
+        }
+    }
 }
 
 pub const RED: usize = 0;
@@ -53,147 +346,360 @@ impl Board {
         self.horizontal_walls.len() / self.width + 1
     }
 
-    pub fn generate(width: usize, height: usize) -> Self {
+    /// Generates a random board. The same
+    /// seed always yields the same board.
+    pub fn generate(options: &GenerateOptions, rng: &mut Rng) -> Result<Self, GenerateError> {
+        options.validate()?;
+
+        let GenerateOptions { width, height, .. } = *options;
         let mut board = Board {
             width,
             horizontal_walls: vec![false; width * (height - 1)],
             vertical_walls: vec![false; (width - 1) * height],
-            initial_positions: [0, 1, 2, 3, 4]
+            center: options.center,
+            initial_positions: [0, 1, 2, 3, 4],
+            targets: Vec::new(),
+            diagonals: Vec::new(),
+            table: OnceLock::new()
         };
-        
 
-        let mut used_tiles: HashSet<(usize, usize)> = HashSet::new();
+        match options.layout {
+            Layout::Quadrants => quadrant::assemble(&mut board, rng),
+            Layout::Scattered => board.scatter_walls(rng)
+        }
 
-        for _ in 0..16 {
-            let mut i: usize = 0;
-            let mut j: usize = 0;
-            loop {
-                i = rand::uniform(1, board.width - 1);
-                j = rand::uniform(1, board.height() - 1);
-                if false
-                    || used_tiles.contains(&(i+1, j-1))
-                    || used_tiles.contains(&(i+1, j))
-                    || used_tiles.contains(&(i+1, j+1))
-                    || used_tiles.contains(&(i, j-1))
-                    || used_tiles.contains(&(i, j))
-                    || used_tiles.contains(&(i, j+1))
-                    || used_tiles.contains(&(i-1, j-1))
-                    || used_tiles.contains(&(i-1, j))
-                    || used_tiles.contains(&(i-1, j+1)) {
-                    continue;
+        // block off center tiles
+        for tile in 0..width * height {
+            if !board.is_center_tile(tile) {
+                continue;
+            }
+            for side in Direction::ALL {
+                if board.neighbor(tile, side).is_some_and(|n| !board.is_center_tile(n)) {
+                    board.set_wall(tile, side);
                 }
+            }
+        }
+
+        board.place_diagonals(options.diagonals, rng);
+        board.initial_positions = board.place_robots(options.robots, rng);
+        Ok(board)
+    }
+
+    /// Places up to `count` diagonals on random free tiles,
+    /// no two of them adjacent, in random colors.
+    fn place_diagonals(&mut self, count: usize, rng: &mut Rng) {
+        let width = self.width;
+        let mut tiles: Vec<usize> = (0..width * self.height())
+            .filter(|&tile| self.is_free_tile(tile))
+            .collect();
+        shuffle(&mut tiles, rng);
+
+        for tile in tiles {
+            if self.diagonals.len() >= count {
                 break;
             }
-            used_tiles.insert((i, j));
+            let adjacent = self.diagonals.iter().any(|d| {
+                (d.tile % width).abs_diff(tile % width) <= 1 && (d.tile / width).abs_diff(tile / width) <= 1
+            });
+            if adjacent {
+                continue;
+            }
+            self.diagonals.push(Diagonal {
+                tile,
+                robot: rng.uniform(RED, BLUE + 1),
+                orientation: if rng.bool() { Orientation::Slash } else { Orientation::Backslash }
+            });
+        }
+    }
+
+    /// Returns the diagonal on the given tile, if any.
+    pub fn diagonal_at(&self, tile: usize) -> Option<&Diagonal> {
+        self.diagonals.iter().find(|diagonal| diagonal.tile == tile)
+    }
+
+    /// Returns the tile next to `tile` in the given direction,
+    /// or `None` at the edge of the board.
+    pub fn neighbor(&self, tile: usize, direction: Direction) -> Option<usize> {
+        let width = self.width;
+        let (x, y) = (tile % width, tile / width);
+        match direction {
+            Direction::Up => (y > 0).then(|| tile - width),
+            Direction::Down => (y + 1 < self.height()).then(|| tile + width),
+            Direction::Left => (x > 0).then(|| tile - 1),
+            Direction::Right => (x + 1 < width).then(|| tile + 1)
+        }
+    }
+
+    /// Scatters up to 17 target L-walls, no two of them
+    /// adjacent, and two walls along each edge at random.
+    /// Boards too small to fit every L-wall get fewer targets.
+    fn scatter_walls(&mut self, rng: &mut Rng) {
+        let width = self.width;
+        let height = self.height();
+
+        // one token per color and symbol, plus the vortex
+        let mut tokens: Vec<(Option<usize>, Symbol)> = Vec::new();
+        for robot in [RED, YELLOW, GREEN, BLUE] {
+            for symbol in Symbol::COLORED {
+                tokens.push((Some(robot), symbol));
+            }
+        }
+        tokens.push((None, Symbol::Vortex));
+        shuffle(&mut tokens, rng);
+
+        let mut tiles: Vec<usize> = (1..height - 1)
+            .flat_map(|j| (1..width - 1).map(move |i| j * width + i))
+            .filter(|&tile| !self.is_center_tile(tile))
+            .collect();
+        shuffle(&mut tiles, rng);
+
+        let mut used_tiles: HashSet<(usize, usize)> = HashSet::new();
+        let mut tiles = tiles.into_iter();
+
+        for (robot, symbol) in tokens {
+            let Some(tile) = tiles.find(|&tile| {
+                let (i, j) = (tile % width, tile / width);
+                !(j-1..=j+1).any(|j| (i-1..=i+1).any(|i| used_tiles.contains(&(i, j))))
+            }) else {
+                break;
+            };
+            used_tiles.insert((tile % width, tile / width));
 
-            board.horizontal_walls[(j + rand::uniform(0, 2) - 1)*width+i] = true;
-            board.vertical_walls[j*(width-1)+i+ rand::uniform(0, 2) - 1] = true;    
+            self.set_wall(tile, if rng.bool() { Direction::Up } else { Direction::Down });
+            self.set_wall(tile, if rng.bool() { Direction::Left } else { Direction::Right });
+            self.targets.push(Target { tile, robot, symbol });
         }
 
-        board.horizontal_walls[rand::uniform(4, 7)*width] = true;
-        board.horizontal_walls[rand::uniform(9, 12)*width] = true;
-        board.horizontal_walls[rand::uniform(4, 7)*width+(width-1)] = true;
-        board.horizontal_walls[rand::uniform(9, 12)*width+(width-1)] = true;
+        // two walls on each edge, one in each half
+        let edge = |rng: &mut Rng, len: usize| [
+            rng.uniform(len / 4, len / 2 - 1),
+            rng.uniform(len / 2 + 1, usize::max(3 * len / 4, len / 2 + 2)),
+        ];
+        for y in edge(rng, height) {
+            self.set_wall(y * width, Direction::Down);
+        }
+        for y in edge(rng, height) {
+            self.set_wall(y * width + width - 1, Direction::Down);
+        }
+        for x in edge(rng, width) {
+            self.set_wall(x, Direction::Right);
+        }
+        for x in edge(rng, width) {
+            self.set_wall((height - 1) * width + x, Direction::Right);
+        }
+    }
 
-        board.vertical_walls[rand::uniform(4, 7)] = true;
-        board.vertical_walls[rand::uniform(9, 12)] = true;
-        board.vertical_walls[rand::uniform(4, 7)+(height-1)*(width-1)] = true;
-        board.vertical_walls[rand::uniform(9, 12)+(height-1)*(width-1)] = true;
+    /// Places a wall on the given side of a tile.
+    /// Walls along the outside of the board are ignored.
+    pub fn set_wall(&mut self, tile: usize, side: Direction) {
+        self.table.take();
+        let width = self.width;
+        let (x, y) = (tile % width, tile / width);
+        match side {
+            Direction::Up if y > 0 => self.horizontal_walls[(y - 1) * width + x] = true,
+            Direction::Down if y < self.height() - 1 => self.horizontal_walls[y * width + x] = true,
+            Direction::Left if x > 0 => self.vertical_walls[y * (width - 1) + x - 1] = true,
+            Direction::Right if x < width - 1 => self.vertical_walls[y * (width - 1) + x] = true,
+            _ => {}
+        }
+    }
 
-        // block off center tiles
-        board.vertical_walls[width/2 - 2 + (height/2-1)*(width - 1)] = true;
-        board.vertical_walls[width/2 + 0 + (height/2-1)*(width - 1)] = true;
-        board.vertical_walls[width/2 - 2 + (height/2)*(width - 1)] = true;
-        board.vertical_walls[width/2 + 0 + (height/2)*(width - 1)] = true;
-        board.horizontal_walls[width/2 - 1 + (height/2 - 2)*width] = true;
-        board.horizontal_walls[width/2 + 0 + (height/2 - 2)*width] = true;
-        board.horizontal_walls[width/2 - 1 + (height/2 + 0)*width] = true;
-        board.horizontal_walls[width/2 + 0 + (height/2 + 0)*width] = true;
+    /// Returns whether a robot may start on the given tile.
+    fn is_free_tile(&self, tile: usize) -> bool {
+        tile < self.width * self.height()
+            && !self.is_center_tile(tile)
+            && self.target_at(tile).is_none()
+            && self.diagonal_at(tile).is_none()
+    }
 
-        board
+    /// Picks robot positions for this board.
+    pub fn place_robots(&self, placement: RobotPlacement, rng: &mut Rng) -> RobotPositions {
+        let mut positions = match placement {
+            RobotPlacement::Random => [usize::MAX; 5],
+            RobotPlacement::Keep(positions) => positions
+        };
+
+        for robot in 0..positions.len() {
+            let taken = |tile| positions[..robot].contains(&tile);
+            if self.is_free_tile(positions[robot]) && !taken(positions[robot]) {
+                continue;
+            }
+            positions[robot] = loop {
+                let tile = rng.uniform(0, self.width * self.height());
+                if self.is_free_tile(tile) && !taken(tile) && !positions[robot+1..].contains(&tile) {
+                    break tile;
+                }
+            };
+        }
+        positions
     }
 
     /// Returns whether the given tile index
     /// represents a center (blocked-off) tile.
+    ///
+    /// The center is 2 tiles wide on boards of even width
+    /// and 1 tile wide on boards of odd width, and likewise
+    /// for the height.
     pub fn is_center_tile(&self, tile: usize) -> bool {
         let x = tile % self.width;
         let y = tile / self.width;
         let height = self.height();
 
-        self.width / 2 - 1 <= x && x <= self.width / 2 &&
-            height / 2 - 1 <= y && y <= height / 2
+        self.center &&
+            (self.width - 1) / 2 <= x && x <= self.width / 2 &&
+            (height - 1) / 2 <= y && y <= height / 2
+    }
+
+    /// Returns the target on the given tile, if any.
+    pub fn target_at(&self, tile: usize) -> Option<&Target> {
+        self.targets.iter().find(|target| target.tile == tile)
+    }
+
+    /// Returns whether playing `moves` from `positions`
+    /// leaves a robot accepted by `target` on the target tile.
+    pub fn is_solution(&self, mut positions: RobotPositions, target: &Target, moves: &[(usize, Direction)]) -> bool {
+        if moves.is_empty() {
+            return false;
+        }
+        for &(robot, direction) in moves {
+            positions = self.move_robot(positions, robot, direction);
+        }
+        positions.iter().enumerate()
+            .any(|(robot, &pos)| pos == target.tile && target.accepts(robot))
+    }
+
+    /// Returns the precomputed move table,
+    /// building it on first use.
+    pub fn move_table(&self) -> &MoveTable {
+        self.table.get_or_init(|| MoveTable::new(self))
     }
 
     /// Given a robot position, move a robot in
     /// a direction. Returns the new robot positions.
-    pub fn move_robot(&self, mut positions: RobotPositions, robot: usize, direction: Direction) -> RobotPositions {
-        let width = self.width;
-        let height = self.height();
-        match direction {
-            Direction::Down => {
-                let wall_pos = self.horizontal_walls.iter().enumerate()
-                    .filter(|(i, b)| (**b) && (*i % width  == positions[robot] % width))
-                    .map(|(i, _)| i / width)
-                    .filter(|&i| i + 1 > positions[robot] / width)
-                    .next().unwrap_or(height - 1);
-                let robot_pos = positions.iter()
-                    .filter(|&i| i % width == positions[robot] % width)
-                    .map(|i| i / width)
-                    .filter(|&i| i > positions[robot] / width)
-                    .min().map(|i| i - 1).unwrap_or(height - 1);
-                let target_pos = usize::min(wall_pos, robot_pos);
-
-                positions[robot] = positions[robot] % width + target_pos * width;
-                positions   
-            },
-            Direction::Up => {
-                let wall_pos = self.horizontal_walls.iter().enumerate().rev()
-                    .filter(|(i, b)| (**b) && (*i % width  == positions[robot] % width))
-                    .map(|(i, _)| i / width)
-                    .filter(|&i| i < positions[robot] / width)
-                    .next().map(|i| i + 1).unwrap_or(0);
-                let robot_pos = positions.iter()
-                    .filter(|&i| i % width == positions[robot] % width)
-                    .map(|i| i / width)
-                    .filter(|&i| i < positions[robot] / width)
-                    .max().map(|i| i + 1).unwrap_or(0);
-                let target_pos = usize::max(wall_pos, robot_pos);
-
-                positions[robot] = positions[robot] % width + target_pos * width;
-                positions
-            },
-            Direction::Right => {
-                let wall_pos = self.vertical_walls.iter().enumerate()
-                    .filter(|&(i, b)| (*b && i / (width - 1) == positions[robot] / width))
-                    .map(|(i, _)| i % (width - 1))
-                    .filter(|&i| i + 1 > positions[robot] % width)
-                    .next().map(|i| i).unwrap_or(width - 1);
-                let robot_pos = positions.iter()
-                    .filter(|&i| i / width == positions[robot] / width)
-                    .map(|i| i % width)
-                    .filter(|&i| i > positions[robot] % width)
-                    .min().map(|i| i - 1).unwrap_or(width - 1);
-                let target_pos = usize::min(wall_pos, robot_pos);
-
-                positions[robot] = (positions[robot] / width) * width + target_pos;
-                positions
-            },
-            Direction::Left => {
-                let wall_pos = self.vertical_walls.iter().enumerate().rev()
-                    .filter(|&(i, b)| *b && i / (width - 1) == positions[robot] / width)
-                    .map(|(i, _)| i % (width - 1))
-                    .filter(|&i| i < positions[robot] % width)
-                    .next().map(|i| i + 1).unwrap_or(0);
-                let robot_pos = positions.iter()
-                    .filter(|&i| i / width == positions[robot] / width)
-                    .map(|i| i % width)
-                    .filter(|&i| i < positions[robot] % width)
-                    .max().map(|i| i + 1).unwrap_or(0);
-                let target_pos = usize::max(wall_pos, robot_pos);
-
-                positions[robot] = (positions[robot] / width) * width + target_pos;
-                positions
+    pub fn move_robot(&self, positions: RobotPositions, robot: usize, direction: Direction) -> RobotPositions {
+        self.move_table().move_robot(positions, robot, direction)
+    }
+}
+/// Fisher-Yates shuffle.
+pub(crate) fn shuffle<T>(items: &mut [T], rng: &mut Rng) {
+    for i in (1..items.len()).rev() {
+        items.swap(i, rng.uniform(0, i + 1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_is_deterministic() {
+        let a = Board::generate(&GenerateOptions::default(), &mut Rng::new(42)).unwrap();
+        let b = Board::generate(&GenerateOptions::default(), &mut Rng::new(42)).unwrap();
+        assert_eq!(a.horizontal_walls, b.horizontal_walls);
+        assert_eq!(a.vertical_walls, b.vertical_walls);
+        assert_eq!(a.targets, b.targets);
+        assert_eq!(a.initial_positions, b.initial_positions);
+    }
+
+    #[test]
+    fn targets_avoid_center() {
+        for seed in 0..100 {
+            let board = Board::generate(&GenerateOptions::default(), &mut Rng::new(seed)).unwrap();
+            assert_eq!(board.targets.len(), 17);
+            assert!(board.targets.iter().all(|t| !board.is_center_tile(t.tile)));
+        }
+    }
+
+    #[test]
+    fn robots_start_on_free_tiles() {
+        for seed in 0..100 {
+            let board = Board::generate(&GenerateOptions::default(), &mut Rng::new(seed)).unwrap();
+            let positions = board.initial_positions;
+            for (robot, &tile) in positions.iter().enumerate() {
+                assert!(board.is_free_tile(tile));
+                assert!(!positions[robot+1..].contains(&tile));
+            }
+        }
+    }
+
+    #[test]
+    fn keep_placement_moves_blocked_robots() {
+        let board = Board::generate(&GenerateOptions::default(), &mut Rng::new(7)).unwrap();
+        let mut kept = board.initial_positions;
+        kept[0] = board.targets[0].tile;
+        let positions = board.place_robots(RobotPlacement::Keep(kept), &mut Rng::new(8));
+        assert_eq!(positions[1..], kept[1..]);
+        assert!(board.is_free_tile(positions[0]));
+        assert!(!positions[1..].contains(&positions[0]));
+    }
+
+    #[test]
+    fn generates_any_valid_size() {
+        for width in MIN_SIZE..=MAX_SIZE {
+            for height in [MIN_SIZE, 7, 16, 33, MAX_SIZE] {
+                for center in [true, false] {
+                    let options = GenerateOptions {
+                        width, height, center,
+                        layout: Layout::Scattered,
+                        ..Default::default()
+                    };
+                    let board = Board::generate(&options, &mut Rng::new((width * height) as u64)).unwrap();
+                    assert_eq!((board.width, board.height()), (width, height));
+                    assert!(!board.targets.is_empty());
+                }
             }
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        let options = |width, height, layout| GenerateOptions { width, height, layout, ..Default::default() };
+        assert_eq!(Board::generate(&options(2, 16, Layout::Scattered), &mut Rng::new(0)).unwrap_err(),
+            GenerateError::InvalidSize { width: 2, height: 16 });
+        assert_eq!(Board::generate(&options(16, 65, Layout::Scattered), &mut Rng::new(0)).unwrap_err(),
+            GenerateError::InvalidSize { width: 16, height: 65 });
+        assert_eq!(Board::generate(&options(12, 12, Layout::Quadrants), &mut Rng::new(0)).unwrap_err(),
+            GenerateError::UnsupportedLayout { layout: Layout::Quadrants, width: 12, height: 12 });
+    }
+
+    #[test]
+    fn changed_walls_change_moves() {
+        let board = Board::generate(&GenerateOptions::default(), &mut Rng::new(3)).unwrap();
+        let positions = board.initial_positions;
+        let (robot, direction) = (0..positions.len())
+            .flat_map(|robot| Direction::ALL.map(|direction| (robot, direction)))
+            .find(|&(robot, direction)| board.move_robot(positions, robot, direction) != positions)
+            .expect("some robot can move");
+        let moved = board.move_robot(positions, robot, direction);
+
+        // a wall in its way, in a copy made after the table was built
+        let mut walled = board.clone();
+        walled.set_wall(positions[robot], direction);
+        assert_eq!(walled.move_robot(positions, robot, direction), positions);
+        assert_eq!(board.move_robot(positions, robot, direction), moved);
+    }
+
+    #[test]
+    fn received_boards_are_checked() {
+        let board = Board::generate(&GenerateOptions::default(), &mut Rng::new(0)).unwrap();
+        let json = serde_json::to_value(&board).unwrap();
+        let received: Board = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(received.horizontal_walls, board.horizontal_walls);
+
+        let tampered = |f: &dyn Fn(&mut serde_json::Value)| {
+            let mut json = json.clone();
+            f(&mut json);
+            serde_json::from_value::<Board>(json).unwrap_err().to_string()
+        };
+        // too big for the move table, though the walls add up
+        let too_big = tampered(&|json| {
+            json["width"] = 80.into();
+            json["horizontal_walls"] = vec![false; 80 * 15].into();
+            json["vertical_walls"] = vec![false; 79 * 16].into();
+        });
+        assert!(too_big.contains("80x16"), "{}", too_big);
+        assert!(tampered(&|json| json["width"] = 0.into()).contains("not supported"));
+        assert!(tampered(&|json| json["vertical_walls"] = vec![false; 3].into()).contains("walls"));
+        assert!(tampered(&|json| json["initial_positions"][0] = 256.into()).contains("off the board"));
+    }
+}
@@ -0,0 +1,82 @@
+//! Estimating the host's clock from ping round trips.
+//!
+//! A client sends a `Ping` stamped with its own time and the
+//! host answers with a `Pong` carrying the host's time. Assuming
+//! the trip there took as long as the trip back, the host's clock
+//! read `host_time` halfway through the round trip.
+
+/// How many recent samples to keep. The one with the
+/// shortest round trip gives the best estimate.
+const SAMPLES: usize = 8;
+
+/// One ping round trip, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub round_trip: u64,
+    /// Host time minus local time.
+    pub offset: i64,
+}
+
+/// A client's estimate of the host's clock.
+#[derive(Debug, Clone, Default)]
+pub struct Clock {
+    samples: Vec<Sample>,
+}
+
+impl Clock {
+    /// Records a pong for a ping sent at local time `sent`
+    /// and received at local time `received`.
+    pub fn sample(&mut self, sent: u64, host_time: u64, received: u64) {
+        let round_trip = received.saturating_sub(sent);
+        let midpoint = sent + round_trip / 2;
+        if self.samples.len() == SAMPLES {
+            self.samples.remove(0);
+        }
+        self.samples.push(Sample {
+            round_trip,
+            offset: host_time as i64 - midpoint as i64,
+        });
+    }
+
+    /// The most trustworthy recent sample, if any.
+    pub fn best(&self) -> Option<Sample> {
+        self.samples.iter().min_by_key(|sample| sample.round_trip).copied()
+    }
+
+    /// Converts a local time to host time.
+    /// Without samples the clocks are assumed to agree.
+    pub fn host_time(&self, local: u64) -> u64 {
+        let offset = self.best().map_or(0, |sample| sample.offset);
+        local.saturating_add_signed(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_offset() {
+        let mut clock = Clock::default();
+        assert_eq!(clock.host_time(1000), 1000);
+
+        // host is 5s ahead, 100ms each way
+        clock.sample(1000, 6100, 1200);
+        assert_eq!(clock.host_time(2000), 7000);
+
+        // a slow, lopsided trip doesn't replace the better sample
+        clock.sample(3000, 8900, 4000);
+        assert_eq!(clock.best().unwrap().round_trip, 200);
+        assert_eq!(clock.host_time(2000), 7000);
+    }
+
+    #[test]
+    fn forgets_old_samples() {
+        let mut clock = Clock::default();
+        clock.sample(0, 0, 10);
+        for i in 0..SAMPLES as u64 {
+            clock.sample(i * 1000, i * 1000 + 2000 + 50, i * 1000 + 100);
+        }
+        assert_eq!(clock.best().unwrap().offset, 2000);
+    }
+}
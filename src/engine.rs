@@ -0,0 +1,380 @@
+use crate::board::{Board, Diagonal, Direction, RobotPositions, MAX_SIZE};
+
+/// Precomputed stopping tiles for every tile and direction.
+///
+/// The table only accounts for walls; robots and diagonals
+/// are handled at move time with bitboards of the robots and
+/// diagonals on the line the moving robot travels along.
+/// Boards may be at most `MAX_SIZE` tiles wide and high.
+#[derive(Debug, Clone)]
+pub struct MoveTable {
+    width: usize,
+
+    /// `stops[tile * 4 + direction]` is the tile a lone robot
+    /// on `tile` ends up on when moving in `direction`.
+    stops: Vec<u16>,
+
+    /// Bitboards of diagonal tiles in each row, indexed by x.
+    diagonal_rows: Vec<u64>,
+
+    /// Bitboards of diagonal tiles in each column, indexed by y.
+    diagonal_columns: Vec<u64>,
+
+    /// The diagonal on each tile, if any.
+    diagonals: Vec<Option<Diagonal>>,
+}
+
+impl MoveTable {
+    pub fn new(board: &Board) -> Self {
+        let width = board.width;
+        let height = board.height();
+        // larger boards would overflow the line bitboards
+        assert!(width <= MAX_SIZE && height <= MAX_SIZE, "a {}x{} board is too large to move on", width, height);
+
+        let wall_right = |x: usize, y: usize| board.vertical_walls[y * (width - 1) + x];
+        let wall_below = |x: usize, y: usize| board.horizontal_walls[y * width + x];
+
+        let mut stops = vec![0; width * height * 4];
+        let mut set = |x: usize, y: usize, direction: Direction, stop: usize| {
+            stops[(y * width + x) * 4 + direction as usize] = stop as u16;
+        };
+
+        for y in 0..height {
+            let mut stop = 0;
+            for x in 0..width {
+                if x == 0 || wall_right(x - 1, y) { stop = x; }
+                set(x, y, Direction::Left, y * width + stop);
+            }
+            let mut stop = width - 1;
+            for x in (0..width).rev() {
+                if x == width - 1 || wall_right(x, y) { stop = x; }
+                set(x, y, Direction::Right, y * width + stop);
+            }
+        }
+
+        for x in 0..width {
+            let mut stop = 0;
+            for y in 0..height {
+                if y == 0 || wall_below(x, y - 1) { stop = y; }
+                set(x, y, Direction::Up, stop * width + x);
+            }
+            let mut stop = height - 1;
+            for y in (0..height).rev() {
+                if y == height - 1 || wall_below(x, y) { stop = y; }
+                set(x, y, Direction::Down, stop * width + x);
+            }
+        }
+
+        let mut diagonal_rows = vec![0; height];
+        let mut diagonal_columns = vec![0; width];
+        let mut diagonals = vec![None; width * height];
+        for &diagonal in &board.diagonals {
+            let (x, y) = (diagonal.tile % width, diagonal.tile / width);
+            diagonal_rows[y] |= 1 << x;
+            diagonal_columns[x] |= 1 << y;
+            diagonals[diagonal.tile] = Some(diagonal);
+        }
+
+        MoveTable { width, stops, diagonal_rows, diagonal_columns, diagonals }
+    }
+
+    /// Returns the tile a lone robot on `tile`
+    /// stops on when moving in `direction`.
+    pub fn stop(&self, tile: usize, direction: Direction) -> usize {
+        self.stops[tile * 4 + direction as usize] as usize
+    }
+
+    /// Given a robot position, move a robot in
+    /// a direction. Returns the new robot positions.
+    pub fn move_robot(&self, mut positions: RobotPositions, robot: usize, mut direction: Direction) -> RobotPositions {
+        let mut tile = positions[robot];
+
+        // Diagonals can send a robot around in circles,
+        // but it can't leave a diagonal the same way twice
+        // without looping forever, so it stops there instead.
+        // Empty until the first diagonal, so plain moves don't allocate.
+        let mut visited: Vec<(usize, usize)> = Vec::new();
+        loop {
+            let (end, diagonal) = self.slide(&positions, robot, tile, direction);
+            tile = end;
+            match diagonal {
+                Some(diagonal) if diagonal.robot != robot => direction = diagonal.orientation.deflect(direction),
+                Some(_) => {},
+                None => break,
+            }
+            if visited.contains(&(tile, direction as usize)) {
+                break;
+            }
+            visited.push((tile, direction as usize));
+        }
+
+        positions[robot] = tile;
+        positions
+    }
+
+    /// Slides `robot` from `tile` in a straight line until it
+    /// stops at a wall or another robot, or enters a diagonal.
+    /// Returns where it ended up and the diagonal it entered.
+    fn slide(&self, positions: &RobotPositions, robot: usize, tile: usize, direction: Direction) -> (usize, Option<Diagonal>) {
+        let width = self.width;
+        let stop = self.stop(tile, direction);
+        if stop == tile {
+            return (tile, None);
+        }
+
+        // Bitboards of the other robots and the diagonals on the
+        // line of travel, indexed by coordinate along that line.
+        let others = positions.iter().enumerate()
+            .filter(|&(other, _)| other != robot)
+            .map(|(_, &p)| p);
+        let vertical = matches!(direction, Direction::Up | Direction::Down);
+        let (robots, diagonals, coord, stop_coord) = if vertical {
+            let x = tile % width;
+            let robots = others
+                .filter(|&p| p % width == x)
+                .fold(0u64, |line, p| line | 1 << (p / width));
+            (robots, self.diagonal_columns[x], tile / width, stop / width)
+        } else {
+            let y = tile / width;
+            let robots = others
+                .filter(|&p| p / width == y)
+                .fold(0u64, |line, p| line | 1 << (p % width));
+            (robots, self.diagonal_rows[y], tile % width, stop % width)
+        };
+
+        let path = match direction {
+            Direction::Down | Direction::Right => span(coord + 1, stop_coord),
+            Direction::Up | Direction::Left => span(stop_coord, coord - 1),
+        };
+        let events = (robots | diagonals) & path;
+        let (coord, entered) = if events == 0 {
+            (stop_coord, false)
+        } else {
+            let (nearest, before) = match direction {
+                Direction::Down | Direction::Right => {
+                    let nearest = events.trailing_zeros() as usize;
+                    (nearest, nearest - 1)
+                },
+                Direction::Up | Direction::Left => {
+                    let nearest = 63 - events.leading_zeros() as usize;
+                    (nearest, nearest + 1)
+                },
+            };
+            // a robot standing on a diagonal blocks it
+            if robots & 1 << nearest != 0 { (before, false) } else { (nearest, true) }
+        };
+
+        let end = if vertical {
+            coord * width + tile % width
+        } else {
+            (tile / width) * width + coord
+        };
+        (end, if entered { self.diagonals[end] } else { None })
+    }
+}
+
+/// Returns a mask with bits `lo..=hi` set.
+fn span(lo: usize, hi: usize) -> u64 {
+    (u64::MAX >> (63 - hi)) & (u64::MAX << lo)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use crate::board::{Board, Direction, RobotPositions};
+    use crate::rand::Rng;
+
+    /// The original wall-scanning implementation,
+    /// kept as an oracle for the move table.
+    #[allow(clippy::filter_next, clippy::map_identity, unused_parens)]
+    fn reference_move_robot(board: &Board, mut positions: RobotPositions, robot: usize, direction: Direction) -> RobotPositions {
+        let width = board.width;
+        let height = board.height();
+        match direction {
+            Direction::Down => {
+                let wall_pos = board.horizontal_walls.iter().enumerate()
+                    .filter(|(i, b)| (**b) && (*i % width  == positions[robot] % width))
+                    .map(|(i, _)| i / width)
+                    .filter(|&i| i + 1 > positions[robot] / width)
+                    .next().unwrap_or(height - 1);
+                let robot_pos = positions.iter()
+                    .filter(|&i| i % width == positions[robot] % width)
+                    .map(|i| i / width)
+                    .filter(|&i| i > positions[robot] / width)
+                    .min().map(|i| i - 1).unwrap_or(height - 1);
+                let target_pos = usize::min(wall_pos, robot_pos);
+
+                positions[robot] = positions[robot] % width + target_pos * width;
+                positions
+            },
+            Direction::Up => {
+                let wall_pos = board.horizontal_walls.iter().enumerate().rev()
+                    .filter(|(i, b)| (**b) && (*i % width  == positions[robot] % width))
+                    .map(|(i, _)| i / width)
+                    .filter(|&i| i < positions[robot] / width)
+                    .next().map(|i| i + 1).unwrap_or(0);
+                let robot_pos = positions.iter()
+                    .filter(|&i| i % width == positions[robot] % width)
+                    .map(|i| i / width)
+                    .filter(|&i| i < positions[robot] / width)
+                    .max().map(|i| i + 1).unwrap_or(0);
+                let target_pos = usize::max(wall_pos, robot_pos);
+
+                positions[robot] = positions[robot] % width + target_pos * width;
+                positions
+            },
+            Direction::Right => {
+                let wall_pos = board.vertical_walls.iter().enumerate()
+                    .filter(|&(i, b)| (*b && i / (width - 1) == positions[robot] / width))
+                    .map(|(i, _)| i % (width - 1))
+                    .filter(|&i| i + 1 > positions[robot] % width)
+                    .next().map(|i| i).unwrap_or(width - 1);
+                let robot_pos = positions.iter()
+                    .filter(|&i| i / width == positions[robot] / width)
+                    .map(|i| i % width)
+                    .filter(|&i| i > positions[robot] % width)
+                    .min().map(|i| i - 1).unwrap_or(width - 1);
+                let target_pos = usize::min(wall_pos, robot_pos);
+
+                positions[robot] = (positions[robot] / width) * width + target_pos;
+                positions
+            },
+            Direction::Left => {
+                let wall_pos = board.vertical_walls.iter().enumerate().rev()
+                    .filter(|&(i, b)| *b && i / (width - 1) == positions[robot] / width)
+                    .map(|(i, _)| i % (width - 1))
+                    .filter(|&i| i < positions[robot] % width)
+                    .next().map(|i| i + 1).unwrap_or(0);
+                let robot_pos = positions.iter()
+                    .filter(|&i| i / width == positions[robot] / width)
+                    .map(|i| i % width)
+                    .filter(|&i| i < positions[robot] % width)
+                    .max().map(|i| i + 1).unwrap_or(0);
+                let target_pos = usize::max(wall_pos, robot_pos);
+
+                positions[robot] = (positions[robot] / width) * width + target_pos;
+                positions
+            }
+        }
+    }
+
+    fn random_board(rng: &mut Rng, width: usize, height: usize) -> Board {
+        let mut board = Board {
+            width,
+            horizontal_walls: vec![false; width * (height - 1)],
+            vertical_walls: vec![false; (width - 1) * height],
+            center: false,
+            initial_positions: [0, 1, 2, 3, 4],
+            targets: Vec::new(),
+            diagonals: Vec::new(),
+            table: Default::default(),
+        };
+        for wall in board.horizontal_walls.iter_mut().chain(board.vertical_walls.iter_mut()) {
+            *wall = rng.uniform(0, 6) == 0;
+        }
+        board
+    }
+
+    fn random_positions(rng: &mut Rng, board: &Board) -> RobotPositions {
+        let tiles = board.width * board.height();
+        let mut positions = [usize::MAX; 5];
+        for i in 0..positions.len() {
+            positions[i] = loop {
+                let tile = rng.uniform(0, tiles);
+                if !positions.contains(&tile) { break tile; }
+            };
+        }
+        positions
+    }
+
+    #[test]
+    fn matches_reference() {
+        let mut rng = Rng::new(1);
+        for (width, height) in [(16, 16), (8, 12), (13, 5), (64, 64)] {
+            for _ in 0..50 {
+                let board = random_board(&mut rng, width, height);
+                let table = MoveTable::new(&board);
+                for _ in 0..50 {
+                    let positions = random_positions(&mut rng, &board);
+                    for robot in 0..positions.len() {
+                        for direction in Direction::ALL {
+                            assert_eq!(
+                                table.move_robot(positions, robot, direction),
+                                reference_move_robot(&board, positions, robot, direction),
+                                "{:?} from {:?} on {}x{}", direction, positions, width, height);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn open_board(diagonals: Vec<Diagonal>) -> Board {
+        let mut board = random_board(&mut Rng::new(0), 8, 8);
+        board.horizontal_walls.fill(false);
+        board.vertical_walls.fill(false);
+        board.diagonals = diagonals;
+        board
+    }
+
+    #[test]
+    fn diagonals_deflect_other_colors() {
+        use crate::board::Orientation::*;
+        let board = open_board(vec![Diagonal { tile: 8 + 5, robot: 0, orientation: Slash }]);
+        let positions = [8 + 1, 60, 61, 62, 63];
+
+        // red passes straight through its own diagonal
+        assert_eq!(board.move_robot(positions, 0, Direction::Right)[0], 8 + 7);
+
+        // yellow is deflected up the column
+        let positions = [60, 8 + 1, 61, 62, 63];
+        assert_eq!(board.move_robot(positions, 1, Direction::Right)[1], 5);
+
+        // a robot on the diagonal blocks it like any other robot
+        let positions = [8 + 5, 8 + 1, 61, 62, 63];
+        assert_eq!(board.move_robot(positions, 1, Direction::Right)[1], 8 + 4);
+    }
+
+    #[test]
+    fn diagonal_loops_terminate() {
+        use crate::board::Orientation::*;
+        let board = open_board(vec![
+            Diagonal { tile: 8 + 5, robot: 0, orientation: Backslash },
+            Diagonal { tile: 32 + 5, robot: 0, orientation: Slash },
+            Diagonal { tile: 32 + 1, robot: 0, orientation: Backslash },
+            Diagonal { tile: 8 + 1, robot: 0, orientation: Slash },
+        ]);
+        let positions = [60, 61, 62, 63, 8 + 3];
+        let moved = board.move_robot(positions, 4, Direction::Right);
+        assert_eq!(moved[..4], positions[..4]);
+        // once round the loop and back onto the first diagonal
+        assert_eq!(moved[4], 8 + 5);
+    }
+
+    fn bench_moves(b: &mut test::Bencher, move_robot: impl Fn(&Board, RobotPositions, usize, Direction) -> RobotPositions) {
+        let mut rng = Rng::new(2);
+        let board = random_board(&mut rng, 16, 16);
+        let positions: Vec<_> = (0..64).map(|_| random_positions(&mut rng, &board)).collect();
+        b.iter(|| {
+            for &positions in &positions {
+                for robot in 0..positions.len() {
+                    for direction in Direction::ALL {
+                        test::black_box(move_robot(&board, positions, robot, direction));
+                    }
+                }
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_move_table(b: &mut test::Bencher) {
+        bench_moves(b, |board, positions, robot, direction| board.move_robot(positions, robot, direction));
+    }
+
+    #[bench]
+    fn bench_reference(b: &mut test::Bencher) {
+        bench_moves(b, reference_move_robot);
+    }
+}
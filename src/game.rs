@@ -0,0 +1,1076 @@
+//! The host's side of a game: players, scores and the
+//! bidding round state machine.
+//!
+//! `Game` never touches the network. Each method returns the
+//! messages the host should send, and the caller delivers them.
+
+use std::cmp::{Ordering, Reverse};
+use std::fmt;
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::board::{Board, Direction, GenerateOptions};
+use crate::net::{self, Message};
+use crate::rand::Rng;
+use crate::room::Room;
+
+/// Player ID of the host itself.
+pub const HOST_ID: &str = "host";
+
+/// How long bidding stays open after the first bid, in milliseconds.
+pub const BID_DURATION: u64 = 60_000;
+
+/// How far a client's bid timestamp may fall before the
+/// host received it, in milliseconds. Stamps older than this
+/// are treated as this old, so a client can't backdate a bid.
+pub const MAX_BID_DELAY: u64 = 2_000;
+
+/// How long a bidder who is away when their turn comes, as after
+/// the host changes, has to come back, in milliseconds.
+pub const REJOIN_WAIT: u64 = 15_000;
+
+/// How long the host keeps the score of a player who left,
+/// for them to reconnect and reclaim it, in milliseconds.
+pub const REJOIN_GRACE: u64 = 120_000;
+
+/// How many chat messages each player may send
+/// within `CHAT_WINDOW` milliseconds.
+pub const CHAT_BURST: usize = 5;
+pub const CHAT_WINDOW: u64 = 10_000;
+
+/// Longer chat messages are cut short, in characters.
+pub const CHAT_LENGTH: usize = 500;
+
+/// Where a round is at.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum Phase {
+    /// No target is revealed.
+    #[default]
+    Idle,
+    /// A target is revealed and nobody has bid yet.
+    Revealed,
+    /// Bids are open until `end_time`.
+    Bidding { end_time: u64 },
+    /// `player` is demonstrating a solution in `bid` moves.
+    Evaluating { player: String, bid: u8 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bid {
+    pub timestamp: u64,
+    pub bid: u8,
+    pub player: String,
+}
+
+impl PartialOrd for Bid {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Bid {
+    /// Lower bids come first; equal bids
+    /// are ordered by who bid first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.bid.cmp(&other.bid)
+            .then(self.timestamp.cmp(&other.timestamp))
+            .then_with(|| self.player.cmp(&other.player))
+    }
+}
+
+/// Why the host rejected a client's message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    /// The sender hasn't joined the game.
+    UnknownPlayer,
+    /// The sender is only watching.
+    Spectator,
+    /// Only the host sends this kind of message.
+    HostOnly,
+    /// Bids are only taken while a target is revealed.
+    NotBidding,
+    /// Bids must be at least one move.
+    ZeroBid,
+    /// Only the player being evaluated can demonstrate.
+    NotYourTurn,
+    /// The sender chatted more than `CHAT_BURST` times in `CHAT_WINDOW`.
+    TooChatty,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Rejection::UnknownPlayer => "you are not in this game",
+            Rejection::Spectator => "spectators can't play",
+            Rejection::HostOnly => "only the host can send that",
+            Rejection::NotBidding => "bidding is not open",
+            Rejection::ZeroBid => "a bid must be at least one move",
+            Rejection::NotYourTurn => "it is not your turn to demonstrate",
+            Rejection::TooChatty => "you're sending messages too fast",
+        })
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+/// Who a message is for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recipient {
+    All,
+    Player(String),
+}
+
+/// Messages for the host to deliver, in order.
+pub type Outbox = Vec<(Recipient, Message)>;
+
+#[derive(Debug, Clone)]
+pub struct Game {
+    pub board: Board,
+
+    /// Index into `board.targets` of this round's target.
+    pub target: Option<usize>,
+
+    /// Player names by ID.
+    pub players: BTreeMap<String, String>,
+    pub scores: BTreeMap<String, u32>,
+
+    /// Names of those watching without playing, by ID.
+    pub spectators: BTreeMap<String, String>,
+    pub phase: Phase,
+
+    /// Each player's current bid this round.
+    pub bids: BTreeMap<String, Bid>,
+
+    /// Bids left to evaluate, lowest first.
+    queue: BinaryHeap<Reverse<Bid>>,
+
+    /// Targets already played on this board.
+    used_targets: Vec<usize>,
+
+    /// Each player's token, for those who sent one.
+    tokens: BTreeMap<String, String>,
+
+    /// Scores of players who left, by token, with
+    /// the time until which they can be reclaimed.
+    away: BTreeMap<String, (u32, u64)>,
+
+    /// When each player last chatted, within `CHAT_WINDOW`.
+    chatted: BTreeMap<String, VecDeque<u64>>,
+
+    pub bid_duration: u64,
+
+    /// How long each bidder gets to demonstrate, in milliseconds.
+    /// Without one, a bidder keeps the turn until the host skips them.
+    pub eval_duration: Option<u64>,
+
+    /// When the current demonstration runs out, once `tick` has seen it start.
+    eval_deadline: Option<u64>,
+
+    /// How to generate a new board once every target on this one
+    /// has been played. Without them, the targets are played again.
+    pub new_boards: Option<GenerateOptions>,
+
+    rng: Rng,
+}
+
+impl Game {
+    pub fn new(board: Board, rng: Rng) -> Self {
+        Game {
+            board,
+            target: None,
+            players: BTreeMap::new(),
+            scores: BTreeMap::new(),
+            spectators: BTreeMap::new(),
+            phase: Phase::Idle,
+            bids: BTreeMap::new(),
+            queue: BinaryHeap::new(),
+            used_targets: Vec::new(),
+            tokens: BTreeMap::new(),
+            away: BTreeMap::new(),
+            chatted: BTreeMap::new(),
+            bid_duration: BID_DURATION,
+            eval_duration: None,
+            eval_deadline: None,
+            new_boards: None,
+            rng,
+        }
+    }
+
+    /// Picks up a game from a client's view of it, for when that
+    /// client takes over as host. Its player becomes `HOST_ID`.
+    /// Scores and bids carry over to be reclaimed as the other
+    /// players reconnect and join again. Bidders get `REJOIN_WAIT`
+    /// to come back once it's their turn.
+    pub fn resume(board: Board, room: &Room, rng: Rng) -> Self {
+        let rename = |id: &str| if id == room.me { HOST_ID.to_string() } else { id.to_string() };
+        let rename_bid = |bid: &Bid| Bid { player: rename(&bid.player), ..bid.clone() };
+
+        let mut game = Game::new(board, rng);
+        game.target = room.target;
+        game.used_targets.extend(room.target);
+        game.scores = room.scores.iter().map(|(id, &score)| (rename(id), score)).collect();
+        game.bids = room.bids.values()
+            .filter(|bid| room.players.contains_key(&bid.player))
+            .map(|bid| (rename(&bid.player), rename_bid(bid)))
+            .collect();
+
+        match &room.phase {
+            Phase::Evaluating { player, .. } => {
+                // the old host said who was still to come
+                let current = room.bids.get(player).map(rename_bid);
+                game.queue = room.queue.iter()
+                    .filter_map(|player| game.bids.get(&rename(player)))
+                    .cloned()
+                    .map(Reverse)
+                    .collect();
+                match current {
+                    Some(current) if game.bids.contains_key(&current.player) => {
+                        game.phase = Phase::Evaluating { player: current.player, bid: current.bid };
+                    },
+                    // the evaluated player went away with the old host;
+                    // everyone hears the outcome when they join
+                    _ => { game.next_bidder(); },
+                }
+            },
+            phase => game.phase = phase.clone(),
+        }
+        game
+    }
+
+    fn board_state(&self) -> Message {
+        Message::BoardState(net::BoardStateMessage {
+            board: self.board.clone(),
+            target: self.target,
+        })
+    }
+
+    /// Adds a player, telling everyone about them
+    /// and catching them up on the current round.
+    pub fn join(&mut self, id: &str, name: &str) -> Outbox {
+        self.players.insert(id.to_string(), name.to_string());
+        if self.is_evaluating(id) {
+            // they came back in time, and get a full turn
+            self.eval_deadline = None;
+        }
+        let score = *self.scores.entry(id.to_string()).or_insert(0);
+
+        let mut outbox = vec![(Recipient::All, Message::PlayerJoin(net::PlayerJoinMessage {
+            ids: vec![id.to_string()],
+            names: vec![name.to_string()],
+            scores: vec![score],
+        }))];
+
+        outbox.extend(self.catch_up(id));
+        outbox
+    }
+
+    /// Adds a spectator, who sees the game without
+    /// playing or being listed among the players.
+    pub fn watch(&mut self, id: &str, name: &str) -> Outbox {
+        self.spectators.insert(id.to_string(), name.to_string());
+        self.catch_up(id)
+    }
+
+    /// Messages that bring a newcomer up to date on the
+    /// board, the players and the current round.
+    fn catch_up(&self, id: &str) -> Outbox {
+        let to = Recipient::Player(id.to_string());
+        let mut outbox = vec![(to.clone(), self.board_state())];
+        outbox.push((to.clone(), Message::PlayerJoin(net::PlayerJoinMessage {
+            ids: self.players.keys().cloned().collect(),
+            names: self.players.values().cloned().collect(),
+            scores: self.players.keys().map(|id| self.scores.get(id).copied().unwrap_or(0)).collect(),
+        })));
+        outbox.extend(self.round_state().into_iter().map(|message| (to.clone(), message)));
+        outbox
+    }
+
+    /// Messages that bring a client up to date on the
+    /// current round, after it has the board state.
+    fn round_state(&self) -> Vec<Message> {
+        let bids = self.bids.values().map(|bid| Message::UpdateBid(net::UpdateBidMessage {
+            player: bid.player.clone(),
+            bid: bid.bid,
+            timestamp: Some(bid.timestamp),
+        }));
+        match &self.phase {
+            Phase::Bidding { end_time } => {
+                let mut messages = vec![Message::StartBid(net::StartBidMessage { end_time: *end_time })];
+                messages.extend(bids);
+                messages
+            },
+            Phase::Evaluating { player, .. } => {
+                let mut messages: Vec<_> = bids.collect();
+                messages.push(Message::StartEval(net::StartEvalMessage { player: player.clone(), queue: self.queued() }));
+                messages
+            },
+            Phase::Idle | Phase::Revealed => Vec::new(),
+        }
+    }
+
+    /// Ties player `id` to `token`, giving them back the score
+    /// of whoever left with it recently. A player still holding
+    /// it is the same one on a connection they left behind, and
+    /// is taken over: they leave, and `id` gets their score.
+    /// Call before `join`.
+    pub fn claim(&mut self, id: &str, token: &str) -> Outbox {
+        if token.is_empty() {
+            return Vec::new();
+        }
+        let holder = self.tokens.iter()
+            .find(|&(other, theirs)| other != id && theirs == token)
+            .map(|(other, _)| other.clone());
+        let outbox = holder.map(|holder| self.leave(&holder, 0)).unwrap_or_default();
+        if let Some((score, _)) = self.away.remove(token) {
+            self.scores.insert(id.to_string(), score);
+        }
+        self.tokens.insert(id.to_string(), token.to_string());
+        outbox
+    }
+
+    /// Removes a player or spectator at time `now`. A player's
+    /// score is kept for `REJOIN_GRACE` if they have a token,
+    /// and if they were demonstrating a solution, their bid fails.
+    pub fn leave(&mut self, id: &str, now: u64) -> Outbox {
+        self.chatted.remove(id);
+        if self.spectators.remove(id).is_some() {
+            return Vec::new();
+        }
+        self.depart(id, now, None)
+    }
+
+    /// Removes a player or spectator for good, telling everyone,
+    /// including them, why. Their score isn't kept.
+    pub fn kick(&mut self, id: &str, reason: &str) -> Outbox {
+        self.tokens.remove(id);
+        self.chatted.remove(id);
+        if self.spectators.remove(id).is_some() {
+            return vec![(Recipient::Player(id.to_string()), Message::PlayerLeave(net::PlayerLeaveMessage {
+                id: id.to_string(),
+                reason: Some(reason.to_string()),
+            }))];
+        }
+        self.depart(id, 0, Some(reason.to_string()))
+    }
+
+    fn depart(&mut self, id: &str, now: u64, reason: Option<String>) -> Outbox {
+        if self.players.remove(id).is_none() {
+            return Vec::new();
+        }
+        let score = self.scores.remove(id).unwrap_or(0);
+        if let Some(token) = self.tokens.remove(id) {
+            self.away.insert(token, (score, now + REJOIN_GRACE));
+        }
+        self.bids.remove(id);
+        self.queue.retain(|Reverse(bid)| bid.player != id);
+
+        let mut outbox = vec![(Recipient::All, Message::PlayerLeave(net::PlayerLeaveMessage {
+            id: id.to_string(),
+            reason,
+        }))];
+        if self.is_evaluating(id) {
+            outbox.extend(self.next_bidder());
+        }
+        outbox
+    }
+
+    /// Replaces the board, abandoning the current round.
+    pub fn set_board(&mut self, board: Board) -> Outbox {
+        self.board = board;
+        self.target = None;
+        self.used_targets.clear();
+        self.reset_round();
+        vec![(Recipient::All, self.board_state())]
+    }
+
+    fn reset_round(&mut self) {
+        self.phase = Phase::Idle;
+        self.bids.clear();
+        self.queue.clear();
+    }
+
+    /// Reveals a target not yet played on this board. Once
+    /// every target has been played, starts over on a new
+    /// board if `new_boards` says how, or else on this one.
+    pub fn start_round(&mut self) -> Outbox {
+        if self.used_targets.len() >= self.board.targets.len() {
+            self.used_targets.clear();
+            if let Some(board) = self.new_boards.as_ref().and_then(|options| Board::generate(options, &mut self.rng).ok()) {
+                self.board = board;
+            }
+        }
+        let unused: Vec<usize> = (0..self.board.targets.len())
+            .filter(|i| !self.used_targets.contains(i))
+            .collect();
+        if unused.is_empty() {
+            return Vec::new();
+        }
+
+        let target = unused[self.rng.uniform(0, unused.len())];
+        self.used_targets.push(target);
+        self.target = Some(target);
+        self.reset_round();
+        self.phase = Phase::Revealed;
+        vec![(Recipient::All, self.board_state())]
+    }
+
+    /// Handles a message from a client, answering
+    /// with an error if it isn't valid right now.
+    pub fn handle(&mut self, from: &str, message: Message, now: u64) -> Outbox {
+        if let Err(rejection) = self.check(from, &message, now) {
+            return vec![(Recipient::Player(from.to_string()), Message::Error(net::ErrorMessage {
+                reason: rejection.to_string(),
+            }))];
+        }
+
+        match message {
+            Message::Ping(msg) => vec![(Recipient::Player(from.to_string()), Message::Pong(net::PongMessage {
+                sent: msg.sent,
+                host_time: now,
+            }))],
+            Message::MakeBid(msg) => {
+                let timestamp = msg.timestamp.map_or(now, |t| t.clamp(now.saturating_sub(MAX_BID_DELAY), now));
+                self.bid(from, msg.bid, timestamp)
+            },
+            Message::DemoMove(msg) => self.demo_move(from, msg.robot, msg.direction),
+            Message::DemoReset(_) => self.demo_reset(from),
+            Message::SubmitMoves(msg) => self.submit(from, msg.moves),
+            Message::Chat(msg) => self.chat(from, &msg.text, now),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Checks that a client's message is valid from this
+    /// sender in the current phase, at host time `now`.
+    pub fn check(&self, from: &str, message: &Message, now: u64) -> Result<(), Rejection> {
+        match message {
+            // clients measure the clock before they have joined
+            Message::Ping(_) => return Ok(()),
+            // spectators can talk too
+            Message::Chat(_) if self.spectators.contains_key(from) => return Ok(()),
+            Message::Chat(_)
+            | Message::MakeBid(_)
+            | Message::DemoMove(_)
+            | Message::DemoReset(_)
+            | Message::SubmitMoves(_) => {},
+            _ => return Err(Rejection::HostOnly),
+        }
+
+        if self.spectators.contains_key(from) {
+            return Err(Rejection::Spectator);
+        }
+        if !self.players.contains_key(from) {
+            return Err(Rejection::UnknownPlayer);
+        }
+        match message {
+            Message::MakeBid(msg) => {
+                // bidding closes at `end_time`, even if
+                // `tick` hasn't moved the phase on yet
+                match self.phase {
+                    Phase::Revealed => {},
+                    Phase::Bidding { end_time } if now < end_time => {},
+                    _ => return Err(Rejection::NotBidding),
+                }
+                if msg.bid == 0 {
+                    return Err(Rejection::ZeroBid);
+                }
+            },
+            Message::Chat(_) => {},
+            _ if !self.is_evaluating(from) => return Err(Rejection::NotYourTurn),
+            _ => {},
+        }
+        Ok(())
+    }
+
+    /// Passes on a chat message, stamped with who sent it and
+    /// when, unless they've been chatting too fast.
+    pub fn chat(&mut self, from: &str, text: &str, now: u64) -> Outbox {
+        let Some(name) = self.players.get(from).or_else(|| self.spectators.get(from)).cloned() else {
+            return Vec::new();
+        };
+        let text = text.trim();
+        if text.is_empty() {
+            return Vec::new();
+        }
+
+        let recent = self.chatted.entry(from.to_string()).or_default();
+        while recent.front().is_some_and(|&time| time + CHAT_WINDOW <= now) {
+            recent.pop_front();
+        }
+        if recent.len() >= CHAT_BURST {
+            return vec![(Recipient::Player(from.to_string()), Message::Error(net::ErrorMessage {
+                reason: Rejection::TooChatty.to_string(),
+            }))];
+        }
+        recent.push_back(now);
+
+        vec![(Recipient::All, Message::Chat(net::ChatMessage {
+            player: from.to_string(),
+            name,
+            time: now,
+            text: text.chars().take(CHAT_LENGTH).collect(),
+        }))]
+    }
+
+    /// Records a bid. The first bid of a round starts the
+    /// countdown; later bids from the same player replace theirs.
+    pub fn bid(&mut self, player: &str, bid: u8, now: u64) -> Outbox {
+        if bid == 0 || !self.players.contains_key(player) {
+            return Vec::new();
+        }
+
+        let mut outbox = Vec::new();
+        match self.phase {
+            Phase::Revealed => {
+                let end_time = now + self.bid_duration;
+                self.phase = Phase::Bidding { end_time };
+                outbox.push((Recipient::All, Message::StartBid(net::StartBidMessage { end_time })));
+            },
+            Phase::Bidding { .. } => {},
+            _ => return outbox,
+        }
+
+        self.bids.insert(player.to_string(), Bid { timestamp: now, bid, player: player.to_string() });
+        outbox.push((Recipient::All, Message::UpdateBid(net::UpdateBidMessage {
+            player: player.to_string(),
+            bid,
+            timestamp: Some(now),
+        })));
+        outbox
+    }
+
+    /// Advances the round once the bidding or demonstration
+    /// deadline passes, and forgets the scores of players
+    /// who have been away longer than `REJOIN_GRACE`.
+    pub fn tick(&mut self, now: u64) -> Outbox {
+        self.away.retain(|_, &mut (_, until)| until > now);
+        match &self.phase {
+            Phase::Bidding { end_time } if now >= *end_time => {
+                self.queue = self.bids.values().cloned().map(Reverse).collect();
+                self.next_bidder()
+            },
+            Phase::Evaluating { player, .. } if !self.players.contains_key(player) => {
+                let duration = self.eval_duration.map_or(REJOIN_WAIT, |duration| duration.min(REJOIN_WAIT));
+                match self.eval_deadline {
+                    Some(deadline) if now >= deadline => self.resolve(false),
+                    Some(_) => Vec::new(),
+                    None => {
+                        self.eval_deadline = Some(now + duration);
+                        Vec::new()
+                    },
+                }
+            },
+            Phase::Evaluating { .. } => match (self.eval_duration, self.eval_deadline) {
+                (Some(_), Some(deadline)) if now >= deadline => self.resolve(false),
+                (Some(duration), None) => {
+                    self.eval_deadline = Some(now + duration);
+                    Vec::new()
+                },
+                _ => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// Starts evaluating the lowest remaining bid,
+    /// or ends the round if none are left.
+    fn next_bidder(&mut self) -> Outbox {
+        match self.queue.pop() {
+            Some(Reverse(bid)) => {
+                let player = bid.player.clone();
+                self.eval_deadline = None;
+                self.phase = Phase::Evaluating { player: player.clone(), bid: bid.bid };
+                vec![(Recipient::All, Message::StartEval(net::StartEvalMessage { player, queue: self.queued() }))]
+            },
+            None => self.end_round(None),
+        }
+    }
+
+    /// The bidders still to demonstrate, in order.
+    fn queued(&self) -> Vec<String> {
+        let mut bids: Vec<&Bid> = self.queue.iter().map(|Reverse(bid)| bid).collect();
+        bids.sort();
+        bids.into_iter().map(|bid| bid.player.clone()).collect()
+    }
+
+    fn is_evaluating(&self, player: &str) -> bool {
+        matches!(&self.phase, Phase::Evaluating { player: evaluated, .. } if evaluated == player)
+    }
+
+    /// Passes a move of the evaluated player's demonstration on to everyone.
+    pub fn demo_move(&mut self, player: &str, robot: usize, direction: Direction) -> Outbox {
+        if !self.is_evaluating(player) || robot >= self.board.initial_positions.len() {
+            return Vec::new();
+        }
+        vec![(Recipient::All, Message::DemoMove(net::DemoMoveMessage {
+            player: player.to_string(),
+            robot,
+            direction,
+        }))]
+    }
+
+    /// Tells everyone the evaluated player started their demonstration over.
+    pub fn demo_reset(&mut self, player: &str) -> Outbox {
+        if !self.is_evaluating(player) {
+            return Vec::new();
+        }
+        vec![(Recipient::All, Message::DemoReset(net::DemoResetMessage {
+            player: player.to_string(),
+        }))]
+    }
+
+    /// Replays the evaluated player's moves from the starting
+    /// positions. They succeed if there are no more moves than
+    /// they bid and an accepted robot ends on the target.
+    pub fn submit(&mut self, player: &str, moves: Vec<(usize, Direction)>) -> Outbox {
+        let Phase::Evaluating { player: evaluated, bid } = &self.phase else {
+            return Vec::new();
+        };
+        if evaluated != player {
+            return Vec::new();
+        }
+        let Some(target) = self.target.and_then(|i| self.board.targets.get(i)) else {
+            return Vec::new();
+        };
+
+        let positions = self.board.initial_positions;
+        let success = moves.len() <= *bid as usize
+            && moves.iter().all(|&(robot, _)| robot < positions.len())
+            && self.board.is_solution(positions, target, &moves);
+
+        let mut outbox = vec![(Recipient::All, Message::Verdict(net::VerdictMessage {
+            player: player.to_string(),
+            moves,
+            success,
+        }))];
+        outbox.extend(self.resolve(success));
+        outbox
+    }
+
+    /// Settles the demonstration in progress. On success the
+    /// bidder scores; on failure the next bidder gets a turn.
+    pub fn resolve(&mut self, success: bool) -> Outbox {
+        let Phase::Evaluating { player, .. } = &self.phase else {
+            return Vec::new();
+        };
+        if success {
+            let player = player.clone();
+            self.end_round(Some(player))
+        } else {
+            self.next_bidder()
+        }
+    }
+
+    /// Awards the winner, if any, and starts the next round.
+    fn end_round(&mut self, winner: Option<String>) -> Outbox {
+        let score = match &winner {
+            Some(winner) => {
+                let score = self.scores.entry(winner.clone()).or_insert(0);
+                *score += 1;
+                *score
+            },
+            None => 0,
+        };
+        self.reset_round();
+
+        let mut outbox = vec![(Recipient::All, Message::EndRound(net::EndRoundMessage { winner, score }))];
+        outbox.extend(self.start_round());
+        outbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::GenerateOptions;
+
+    fn game() -> Game {
+        let board = Board::generate(&GenerateOptions::default(), &mut Rng::new(1)).unwrap();
+        let mut game = Game::new(board, Rng::new(2));
+        game.join(HOST_ID, "Host");
+        game.join("a", "Alice");
+        game.join("b", "Bob");
+        game
+    }
+
+    fn evaluating(game: &Game) -> Option<&str> {
+        match &game.phase {
+            Phase::Evaluating { player, .. } => Some(player),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn round_lifecycle() {
+        let mut game = game();
+        game.start_round();
+        assert_eq!(game.phase, Phase::Revealed);
+        let first_target = game.target;
+
+        game.bid("a", 7, 1000);
+        assert_eq!(game.phase, Phase::Bidding { end_time: 1000 + BID_DURATION });
+        game.bid("b", 5, 2000);
+        game.bid(HOST_ID, 5, 3000);
+
+        // nothing happens before the deadline
+        assert!(game.tick(1000 + BID_DURATION - 1).is_empty());
+
+        // lowest bid first, ties go to the earlier bid
+        game.tick(1000 + BID_DURATION);
+        assert_eq!(evaluating(&game), Some("b"));
+        game.resolve(false);
+        assert_eq!(evaluating(&game), Some(HOST_ID));
+        game.resolve(false);
+        assert_eq!(evaluating(&game), Some("a"));
+
+        let outbox = game.resolve(true);
+        assert!(outbox.iter().any(|(_, m)| matches!(m, Message::EndRound(net::EndRoundMessage { winner: Some(w), score: 1 }) if w == "a")));
+        assert_eq!(game.scores["a"], 1);
+
+        // the next round starts with a new target
+        assert_eq!(game.phase, Phase::Revealed);
+        assert_ne!(game.target, first_target);
+        assert!(game.bids.is_empty());
+    }
+
+    #[test]
+    fn boards_change_once_every_target_is_played() {
+        let mut game = game();
+        let targets = game.board.targets.len();
+        let mut played = Vec::new();
+        for _ in 0..targets {
+            game.start_round();
+            played.push(game.target.unwrap());
+        }
+        played.sort();
+        assert_eq!(played, (0..targets).collect::<Vec<_>>());
+
+        // the same board comes round again by default
+        let walls = game.board.horizontal_walls.clone();
+        game.start_round();
+        assert_eq!(game.board.horizontal_walls, walls);
+
+        game.new_boards = Some(GenerateOptions::default());
+        for _ in 1..targets {
+            game.start_round();
+        }
+        assert_eq!(game.board.horizontal_walls, walls);
+        let outbox = game.start_round();
+        assert_ne!(game.board.horizontal_walls, walls);
+        assert!(matches!(&outbox[..], [(Recipient::All, Message::BoardState(state))] if state.board.horizontal_walls != walls));
+    }
+
+    #[test]
+    fn round_ends_when_every_bidder_fails() {
+        let mut game = game();
+        game.start_round();
+        game.bid("a", 3, 0);
+        game.tick(BID_DURATION);
+        let outbox = game.resolve(false);
+        assert!(outbox.iter().any(|(_, m)| matches!(m, Message::EndRound(net::EndRoundMessage { winner: None, .. }))));
+        assert_eq!(game.scores["a"], 0);
+    }
+
+    #[test]
+    fn slow_demonstrations_run_out() {
+        let mut game = game();
+        game.eval_duration = Some(1000);
+        game.start_round();
+        game.bid("a", 3, 0);
+        game.bid("b", 4, 0);
+        game.tick(BID_DURATION);
+        assert_eq!(evaluating(&game), Some("a"));
+
+        // the clock starts when the host first ticks
+        game.tick(BID_DURATION + 500);
+        assert!(game.tick(BID_DURATION + 1499).is_empty());
+        game.tick(BID_DURATION + 1500);
+        assert_eq!(evaluating(&game), Some("b"));
+
+        // the next bidder gets a full turn
+        game.tick(BID_DURATION + 1600);
+        game.tick(BID_DURATION + 2599);
+        assert_eq!(evaluating(&game), Some("b"));
+        game.tick(BID_DURATION + 2600);
+        assert_eq!(game.phase, Phase::Revealed);
+    }
+
+    #[test]
+    fn submitted_moves_are_replayed() {
+        let mut game = game();
+        game.start_round();
+        let target = game.board.targets[game.target.unwrap()];
+        let solution = crate::solver::solve(&game.board, game.board.initial_positions, &target, 10)
+            .expect("target is solvable");
+
+        game.bid("a", solution.len() as u8, 0);
+        game.bid("b", solution.len() as u8, 1);
+        game.tick(BID_DURATION);
+        assert_eq!(evaluating(&game), Some("a"));
+
+        // only the evaluated player may submit
+        assert!(game.submit("b", solution.clone()).is_empty());
+
+        // a solution longer than the bid fails
+        let mut long = solution.clone();
+        long.insert(0, (long[0].0, long[0].1));
+        let outbox = game.submit("a", long);
+        assert!(matches!(&outbox[0].1, Message::Verdict(net::VerdictMessage { success: false, .. })));
+        assert_eq!(evaluating(&game), Some("b"));
+
+        // robots that don't exist fail rather than panic
+        let outbox = game.submit("b", vec![(7, Direction::Up)]);
+        assert!(matches!(&outbox[0].1, Message::Verdict(net::VerdictMessage { success: false, .. })));
+
+        game.start_round();
+        let target = game.board.targets[game.target.unwrap()];
+        let solution = crate::solver::solve(&game.board, game.board.initial_positions, &target, 10)
+            .expect("target is solvable");
+        game.bid("b", solution.len() as u8, 0);
+        game.tick(BID_DURATION);
+        let outbox = game.submit("b", solution);
+        assert!(matches!(&outbox[0].1, Message::Verdict(net::VerdictMessage { success: true, .. })));
+        assert_eq!(game.scores["b"], 1);
+    }
+
+    #[test]
+    fn only_the_evaluated_player_demonstrates() {
+        let mut game = game();
+        game.start_round();
+        game.bid("a", 3, 0);
+        game.tick(BID_DURATION);
+
+        assert!(game.demo_move("b", 0, Direction::Up).is_empty());
+        assert!(game.demo_move("a", 9, Direction::Up).is_empty());
+        assert!(game.demo_reset("b").is_empty());
+
+        let outbox = game.handle("a", Message::DemoMove(net::DemoMoveMessage {
+            player: "b".into(),
+            robot: 1,
+            direction: Direction::Left,
+        }), 0);
+        // the host names the sender, whatever the client claimed
+        assert!(matches!(&outbox[..], [(Recipient::All, Message::DemoMove(msg))] if msg.player == "a"));
+    }
+
+    #[test]
+    fn bids_keep_client_timestamps() {
+        let mut game = game();
+        game.start_round();
+        let bid = |bid, timestamp| Message::MakeBid(net::MakeBidMessage { bid, timestamp });
+
+        game.handle("a", bid(4, Some(9_500)), 10_000);
+        // "b" bid earlier but its message took longer to arrive
+        game.handle("b", bid(4, Some(9_400)), 10_200);
+        // backdating is capped
+        game.handle(HOST_ID, bid(4, Some(0)), 11_000);
+        assert_eq!(game.bids[HOST_ID].timestamp, 11_000 - MAX_BID_DELAY);
+        // stamps from the future count as now
+        game.handle("a", bid(3, Some(20_000)), 12_000);
+        assert_eq!(game.bids["a"].timestamp, 12_000);
+
+        game.tick(u64::MAX);
+        assert_eq!(evaluating(&game), Some("a"));
+        game.resolve(false);
+        assert_eq!(evaluating(&game), Some(HOST_ID));
+        game.resolve(false);
+        assert_eq!(evaluating(&game), Some("b"));
+    }
+
+    #[test]
+    fn resumes_from_a_clients_view() {
+        let board = Board::generate(&GenerateOptions::default(), &mut Rng::new(1)).unwrap();
+        let mut game = Game::new(board.clone(), Rng::new(2));
+        let mut room = Room::new("b");
+        let mut outbox = Vec::new();
+        outbox.extend(game.join(HOST_ID, "Host"));
+        outbox.extend(game.join("a", "Alice"));
+        outbox.extend(game.join("b", "Bob"));
+        outbox.extend(game.start_round());
+        outbox.extend(game.bid(HOST_ID, 2, 0));
+        outbox.extend(game.bid("a", 3, 0));
+        outbox.extend(game.bid("b", 4, 0));
+        outbox.extend(game.tick(BID_DURATION));
+        outbox.extend(game.resolve(false));
+        for (_, message) in outbox {
+            room.apply(&message, 0);
+        }
+        assert_eq!(evaluating(&game), Some("a"));
+
+        // Bob takes over from the host
+        assert_eq!(room.elect("old-host").as_deref(), Some("a"));
+        assert_eq!(room.elect("a").as_deref(), Some("b"));
+        let mut resumed = Game::resume(board, &room, Rng::new(3));
+        assert_eq!(resumed.target, game.target);
+        // Alice is gone, so Bob is up next as the host
+        assert_eq!(evaluating(&resumed), Some(HOST_ID));
+        assert_eq!(resumed.bids.keys().collect::<Vec<_>>(), [HOST_ID]);
+
+        resumed.resolve(false);
+        assert_eq!(resumed.phase, Phase::Revealed);
+    }
+
+    #[test]
+    fn resumed_rounds_keep_the_hosts_order() {
+        let mut game = game();
+        let mut outbox = game.join("c", "Carol");
+        outbox.extend(game.start_round());
+        outbox.extend(game.bid(HOST_ID, 2, 0));
+        // a tie, which Carol hears about the other way round
+        outbox.extend(game.bid("b", 3, 100));
+        outbox.extend(game.bid("a", 3, 200));
+        outbox.extend(game.bid("c", 4, 300));
+        outbox.extend(game.tick(BID_DURATION));
+        outbox.extend(game.resolve(false));
+        outbox.extend(game.resolve(false));
+        assert_eq!(evaluating(&game), Some("a"));
+
+        let mut room = Room::new("c");
+        for (i, (_, message)) in outbox.iter().enumerate() {
+            let received = if matches!(message, Message::UpdateBid(bid) if bid.player == "a") { 0 } else { 1_000 + i as u64 };
+            room.apply(message, received);
+        }
+        room.elect("old-host");
+        let mut resumed = Game::resume(game.board.clone(), &room, Rng::new(3));
+        resumed.join(HOST_ID, "Carol");
+        assert_eq!(evaluating(&resumed), Some("a"));
+
+        // Alice doesn't come back in time, and Bob already failed
+        assert!(resumed.tick(0).is_empty());
+        assert!(resumed.tick(REJOIN_WAIT - 1).is_empty());
+        resumed.tick(REJOIN_WAIT);
+        assert_eq!(evaluating(&resumed), Some(HOST_ID));
+    }
+
+    #[test]
+    fn bidders_who_come_back_get_a_full_turn() {
+        let mut game = game();
+        game.eval_duration = Some(REJOIN_WAIT * 2);
+        game.start_round();
+        game.bid("a", 3, 0);
+        game.tick(BID_DURATION);
+        game.players.remove("a");
+
+        game.tick(BID_DURATION);
+        game.join("a", "Alice");
+        game.tick(BID_DURATION + REJOIN_WAIT);
+        assert!(game.tick(BID_DURATION + REJOIN_WAIT * 3 - 1).is_empty());
+        assert_eq!(evaluating(&game), Some("a"));
+    }
+
+    #[test]
+    fn invalid_messages_are_rejected() {
+        let mut game = game();
+        let bid = |bid| Message::MakeBid(net::MakeBidMessage { bid, timestamp: None });
+        let reject = |outbox: Outbox, rejection: Rejection| {
+            assert_eq!(outbox.len(), 1);
+            assert!(matches!(&outbox[0], (Recipient::Player(to), Message::Error(msg))
+                if to == "a" && msg.reason == rejection.to_string()));
+        };
+
+        reject(game.handle("a", bid(3), 0), Rejection::NotBidding);
+        game.start_round();
+        reject(game.handle("a", bid(0), 0), Rejection::ZeroBid);
+        reject(game.handle("a", Message::DemoReset(net::DemoResetMessage { player: String::new() }), 0), Rejection::NotYourTurn);
+        reject(game.handle("a", Message::PlayerLeave(net::PlayerLeaveMessage { id: "b".into(), reason: None }), 0), Rejection::HostOnly);
+
+        game.leave("a", 0);
+        reject(game.handle("a", bid(3), 0), Rejection::UnknownPlayer);
+        assert_eq!(game.phase, Phase::Revealed);
+        assert!(game.bids.is_empty());
+    }
+
+    #[test]
+    fn late_bids_are_rejected_before_the_tick() {
+        let mut game = game();
+        game.start_round();
+        game.bid("a", 3, 0);
+        let late = Message::MakeBid(net::MakeBidMessage { bid: 2, timestamp: Some(BID_DURATION - 1) });
+        let outbox = game.handle("b", late, BID_DURATION);
+        assert!(matches!(&outbox[..], [(_, Message::Error(msg))] if msg.reason == Rejection::NotBidding.to_string()));
+        assert!(!game.bids.contains_key("b"));
+    }
+
+    #[test]
+    fn spectators_watch_without_playing() {
+        let mut game = game();
+        game.start_round();
+        game.bid("a", 3, 0);
+
+        let outbox = game.watch("s", "Sam");
+        assert!(outbox.iter().all(|(to, _)| *to == Recipient::Player("s".into())));
+        assert!(outbox.iter().any(|(_, m)| matches!(m, Message::UpdateBid(bid) if bid.player == "a")));
+        assert!(!game.players.contains_key("s") && !game.scores.contains_key("s"));
+
+        let outbox = game.handle("s", Message::MakeBid(net::MakeBidMessage { bid: 2, timestamp: None }), 0);
+        assert!(matches!(&outbox[..], [(_, Message::Error(error))] if error.reason == Rejection::Spectator.to_string()));
+        assert!(!game.bids.contains_key("s"));
+
+        // nobody is told when a spectator leaves
+        assert!(game.leave("s", 0).is_empty());
+        assert!(game.spectators.is_empty());
+    }
+
+    #[test]
+    fn leaving_bidder_forfeits() {
+        let mut game = game();
+        game.start_round();
+        game.bid("a", 3, 0);
+        game.bid("b", 4, 0);
+        game.tick(BID_DURATION);
+        assert_eq!(evaluating(&game), Some("a"));
+        game.leave("a", 0);
+        assert_eq!(evaluating(&game), Some("b"));
+    }
+
+    #[test]
+    fn chat_is_stamped_and_rate_limited() {
+        let mut game = game();
+        let say = |text: &str| Message::Chat(net::ChatMessage { text: text.into(), ..Default::default() });
+
+        let outbox = game.handle("a", say("  hi  "), 7);
+        let expected = net::ChatMessage { player: "a".into(), name: "Alice".into(), time: 7, text: "hi".into() };
+        assert!(matches!(&outbox[..], [(Recipient::All, Message::Chat(chat))] if *chat == expected));
+        assert!(game.handle("a", say(" "), 7).is_empty());
+
+        for _ in 1..CHAT_BURST {
+            game.handle("a", say("hi"), 8);
+        }
+        let outbox = game.handle("a", say("hi"), 9);
+        assert!(matches!(&outbox[..], [(_, Message::Error(error))] if error.reason == Rejection::TooChatty.to_string()));
+        // others aren't held back, and the limit wears off
+        assert_eq!(game.handle("b", say("hi"), 9).len(), 1);
+        assert_eq!(game.handle("a", say("hi"), 7 + CHAT_WINDOW).len(), 1);
+    }
+
+    #[test]
+    fn kicked_players_lose_their_score() {
+        let mut game = game();
+        game.claim("a", "secret");
+        game.scores.insert("a".into(), 2);
+
+        let outbox = game.kick("a", "Behave.");
+        assert!(matches!(&outbox[..], [(Recipient::All, Message::PlayerLeave(leave))] if leave.reason.as_deref() == Some("Behave.")));
+        game.claim("a2", "secret");
+        game.join("a2", "Alice");
+        assert_eq!(game.scores["a2"], 0);
+    }
+
+    #[test]
+    fn claiming_a_live_token_takes_over() {
+        let mut game = game();
+        game.claim("a", "secret");
+        game.join("a", "Alice");
+        game.scores.insert("a".into(), 3);
+
+        let outbox = game.claim("a2", "secret");
+        assert!(matches!(&outbox[..], [(Recipient::All, Message::PlayerLeave(leave))] if leave.id == "a" && leave.reason.is_none()));
+        game.join("a2", "Alice");
+        assert!(!game.players.contains_key("a"));
+        assert_eq!(game.scores["a2"], 3);
+        // claiming again as the same player changes nothing
+        assert!(game.claim("a2", "secret").is_empty());
+        assert_eq!(game.scores["a2"], 3);
+    }
+}
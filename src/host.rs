@@ -0,0 +1,438 @@
+//! Runs a `Game` over a `Transport`: players join once their
+//! client says hello with a protocol the host speaks, and the
+//! host lets them in. Their messages go to the game, and the
+//! game's replies go back out over the right connections.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+use crate::game::{Game, Outbox, Recipient, HOST_ID};
+use crate::net::{self, Message, Metadata};
+use crate::transport::{Connection, Transport};
+
+/// How long a client that was turned away gets to read why and
+/// hang up, in milliseconds, before the host hangs up on them.
+/// Closing at once could lose the reason: PeerJS drops data that
+/// is still in flight when a connection closes.
+pub const CLOSE_GRACE: u64 = 1_000;
+
+/// Who the host lets into the game.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Admission {
+    /// Joiners must send this in their metadata.
+    pub password: Option<String>,
+
+    /// Joiners wait until the host admits them.
+    pub approve: bool,
+}
+
+type Pending = BTreeMap<String, (Metadata, net::WelcomeMessage)>;
+
+/// Waiting clients and their metadata.
+type Waiting = Vec<(String, Metadata)>;
+
+type Handler<T> = Box<dyn Fn(T)>;
+
+struct Inner<T: Transport> {
+    transport: T,
+    game: RefCell<Game>,
+
+    /// Open connections, each with a key of its own, as a peer
+    /// may connect again before its old connection is closed.
+    conns: RefCell<Vec<(u64, T::Connection)>>,
+    next_key: Cell<u64>,
+
+    /// What each client in the game was welcomed with,
+    /// including the features agreed with them, by peer ID.
+    welcomed: RefCell<HashMap<String, net::WelcomeMessage>>,
+
+    admission: RefCell<Admission>,
+
+    /// Clients waiting to be admitted, by peer ID.
+    pending: RefCell<Pending>,
+
+    /// Told whenever `pending` changes.
+    on_pending: RefCell<Vec<Handler<Waiting>>>,
+
+    /// Clients that were turned away. Nothing
+    /// they send is listened to any more.
+    refused: RefCell<HashSet<String>>,
+
+    /// Connections to close on the first `tick` after the
+    /// given host time, by peer ID.
+    closing: RefCell<Vec<(String, u64)>>,
+
+    /// Peer IDs and session tokens that may not come back.
+    banned: RefCell<HashSet<String>>,
+
+    /// The host's clock, in milliseconds.
+    now: Box<dyn Fn() -> u64>,
+
+    /// Receives the messages meant for the host's own player.
+    local: Box<dyn Fn(Message)>,
+}
+
+/// The host's end of a game.
+pub struct Host<T: Transport> {
+    inner: Rc<Inner<T>>,
+}
+
+impl<T: Transport> Clone for Host<T> {
+    fn clone(&self) -> Self {
+        Host { inner: self.inner.clone() }
+    }
+}
+
+impl<T: Transport> Host<T> {
+    /// Starts hosting `game` on `transport`. Messages for the
+    /// host's own player, `HOST_ID`, are passed to `local`.
+    pub fn new(transport: T, game: Game, now: impl Fn() -> u64 + 'static, local: impl Fn(Message) + 'static) -> Self {
+        let host = Host {
+            inner: Rc::new(Inner {
+                transport: transport.clone(),
+                game: RefCell::new(game),
+                conns: RefCell::new(Vec::new()),
+                next_key: Cell::new(0),
+                welcomed: RefCell::new(HashMap::new()),
+                admission: RefCell::new(Admission::default()),
+                pending: RefCell::new(BTreeMap::new()),
+                on_pending: RefCell::new(Vec::new()),
+                refused: RefCell::new(HashSet::new()),
+                closing: RefCell::new(Vec::new()),
+                banned: RefCell::new(HashSet::new()),
+                now: Box::new(now),
+                local: Box::new(local),
+            }),
+        };
+
+        let weak = Rc::downgrade(&host.inner);
+        transport.on_connection(move |conn| {
+            if let Some(host) = upgrade(&weak) {
+                host.accept(conn);
+            }
+        });
+        host
+    }
+
+    fn accept(&self, conn: T::Connection) {
+        let id = conn.peer();
+        let mut metadata = conn.metadata();
+        if metadata.name.is_empty() {
+            metadata.name = "Anonymous".to_string();
+        }
+        // the peer's old connection is dead, or soon will be
+        for key in self.keys(|old| old.peer() == id) {
+            self.replace(key);
+        }
+        let key = self.inner.next_key.get();
+        self.inner.next_key.set(key + 1);
+        self.inner.conns.borrow_mut().push((key, conn.clone()));
+
+        let weak = Rc::downgrade(&self.inner);
+        let player = id.clone();
+        conn.on_data(move |message| {
+            if let Some(host) = upgrade(&weak).filter(|host| host.is_current(key)) {
+                host.receive(&player, &metadata, message);
+            }
+        });
+
+        let weak = Rc::downgrade(&self.inner);
+        conn.on_close(move || {
+            if let Some(host) = upgrade(&weak).filter(|host| host.is_current(key)) {
+                host.inner.conns.borrow_mut().retain(|&(current, _)| current != key);
+                host.forget(&id);
+                host.update(|game, now| game.leave(&id, now));
+            }
+        });
+    }
+
+    /// The keys of the connections that match `f`.
+    fn keys(&self, f: impl Fn(&T::Connection) -> bool) -> Vec<u64> {
+        self.inner.conns.borrow().iter().filter(|(_, conn)| f(conn)).map(|&(key, _)| key).collect()
+    }
+
+    /// Whether the connection with key `key` is still open
+    /// and hasn't been replaced, so it's worth listening to.
+    fn is_current(&self, key: u64) -> bool {
+        self.inner.conns.borrow().iter().any(|&(current, _)| current == key)
+    }
+
+    /// Closes a connection that a newer one from the same player
+    /// replaces. Anything more from it, even its close, is ignored,
+    /// and the newer connection starts over by saying hello.
+    /// Returns the peer it was from.
+    fn replace(&self, key: u64) -> Option<String> {
+        let index = self.inner.conns.borrow().iter().position(|&(current, _)| current == key)?;
+        let (_, conn) = self.inner.conns.borrow_mut().remove(index);
+        let peer = conn.peer();
+        self.forget(&peer);
+        conn.close();
+        Some(peer)
+    }
+
+    /// Forgets how far a peer got in joining.
+    fn forget(&self, peer: &str) {
+        self.inner.welcomed.borrow_mut().remove(peer);
+        self.inner.refused.borrow_mut().remove(peer);
+        self.inner.closing.borrow_mut().retain(|(closing, _)| closing != peer);
+        if self.inner.pending.borrow_mut().remove(peer).is_some() {
+            self.pending_changed();
+        }
+    }
+
+    /// Handles a message from `player`, who only joins the game
+    /// once they've said hello and been let in. Until then, the
+    /// host answers everything else with why they can't play.
+    fn receive(&self, player: &str, metadata: &Metadata, message: Message) {
+        if self.inner.welcomed.borrow().contains_key(player) {
+            return self.update(|game, now| game.handle(player, message, now));
+        }
+        if self.inner.refused.borrow().contains(player) || self.inner.pending.borrow().contains_key(player) {
+            return;
+        }
+        let banned = {
+            let banned = self.inner.banned.borrow();
+            banned.contains(player) || (!metadata.token.is_empty() && banned.contains(&metadata.token))
+        };
+        if banned {
+            return self.refuse(player, "You were banned from this room.".to_string());
+        }
+        let welcome = match message {
+            Message::Hello(hello) => hello.negotiate(),
+            _ => Err("Your game is too old to join this room. Reload the page to update it.".to_string()),
+        };
+        let admission = self.inner.admission.borrow().clone();
+        match welcome {
+            Err(reason) => self.refuse(player, reason),
+            Ok(_) if admission.password.is_some_and(|password| password != metadata.password) =>
+                self.refuse(player, "This room needs a password, and that wasn't it.".to_string()),
+            Ok(welcome) if admission.approve => {
+                self.inner.pending.borrow_mut().insert(player.to_string(), (metadata.clone(), welcome));
+                self.deliver(vec![(Recipient::Player(player.to_string()), Message::Pending(net::PendingMessage {}))]);
+                self.pending_changed();
+            },
+            Ok(welcome) => self.welcome(player, metadata, welcome),
+        }
+    }
+
+    /// Lets a client into the game. Another peer with the same
+    /// token is the same player on a connection they left behind,
+    /// which this one replaces, taking over their place in the game.
+    fn welcome(&self, player: &str, metadata: &Metadata, mut welcome: net::WelcomeMessage) {
+        let token = &metadata.token;
+        let stale: Vec<String> = self.keys(|conn| !token.is_empty() && conn.peer() != player && conn.metadata().token == *token)
+            .into_iter()
+            .filter_map(|key| self.replace(key))
+            .collect();
+
+        welcome.approve = self.inner.admission.borrow().approve;
+        self.inner.welcomed.borrow_mut().insert(player.to_string(), welcome.clone());
+        self.deliver(vec![(Recipient::Player(player.to_string()), Message::Welcome(welcome))]);
+        self.update(|game, now| {
+            let mut outbox = match metadata.spectator {
+                true => game.watch(player, &metadata.name),
+                false => {
+                    let mut outbox = game.claim(player, token);
+                    outbox.extend(game.join(player, &metadata.name));
+                    outbox
+                },
+            };
+            // a spectator, or a player only watching now, just goes
+            for peer in &stale {
+                outbox.extend(game.leave(peer, now));
+            }
+            outbox
+        });
+    }
+
+    /// Turns a client away, telling them why. The client
+    /// is left to hang up, and hung up on if it doesn't.
+    fn refuse(&self, player: &str, reason: String) {
+        self.inner.refused.borrow_mut().insert(player.to_string());
+        self.deliver(vec![(Recipient::Player(player.to_string()), Message::Error(net::ErrorMessage { reason }))]);
+        self.close_later(player);
+    }
+
+    /// Closes the connection to `peer` once `CLOSE_GRACE` is over.
+    fn close_later(&self, peer: &str) {
+        let at = self.now() + CLOSE_GRACE;
+        self.inner.closing.borrow_mut().push((peer.to_string(), at));
+    }
+
+    pub fn admission(&self) -> Admission {
+        self.inner.admission.borrow().clone()
+    }
+
+    /// Changes who may join from now on. Clients
+    /// already in the game or waiting are unaffected,
+    /// but are told whether joins need approval.
+    pub fn set_admission(&self, admission: Admission) {
+        let approve = admission.approve;
+        if std::mem::replace(&mut *self.inner.admission.borrow_mut(), admission).approve == approve {
+            return;
+        }
+        let outbox = self.inner.welcomed.borrow_mut().iter_mut()
+            .map(|(peer, welcome)| {
+                welcome.approve = approve;
+                (Recipient::Player(peer.clone()), Message::Welcome(welcome.clone()))
+            })
+            .collect();
+        self.deliver(outbox);
+    }
+
+    /// Clients waiting to be admitted, with their metadata.
+    pub fn pending(&self) -> Vec<(String, Metadata)> {
+        self.inner.pending.borrow().iter().map(|(id, (metadata, _))| (id.clone(), metadata.clone())).collect()
+    }
+
+    /// Called with the waiting clients whenever they change.
+    pub fn on_pending(&self, f: impl Fn(Vec<(String, Metadata)>) + 'static) {
+        self.inner.on_pending.borrow_mut().push(Box::new(f));
+    }
+
+    fn pending_changed(&self) {
+        let pending = self.pending();
+        for handler in self.inner.on_pending.borrow().iter() {
+            handler(pending.clone());
+        }
+    }
+
+    /// Lets a waiting client into the game.
+    pub fn admit(&self, peer: &str) {
+        let Some((metadata, welcome)) = self.inner.pending.borrow_mut().remove(peer) else {
+            return;
+        };
+        self.pending_changed();
+        self.welcome(peer, &metadata, welcome);
+    }
+
+    /// Turns a waiting client away, telling them why.
+    pub fn reject(&self, peer: &str, reason: &str) {
+        if self.inner.pending.borrow_mut().remove(peer).is_none() {
+            return;
+        }
+        self.pending_changed();
+        self.refuse(peer, reason.to_string());
+    }
+
+    /// Removes a player or spectator from the game, telling them
+    /// why. Like a refused client, they're left to hang up, and
+    /// nothing more they send is listened to. They may come back.
+    pub fn kick(&self, player: &str, reason: &str) {
+        if player == HOST_ID || !self.inner.welcomed.borrow().contains_key(player) {
+            return;
+        }
+        self.update(|game, _| game.kick(player, reason));
+        self.inner.welcomed.borrow_mut().remove(player);
+        self.inner.refused.borrow_mut().insert(player.to_string());
+        self.close_later(player);
+    }
+
+    /// Kicks a player and refuses them from now on,
+    /// whether they come back as the same peer or
+    /// with the same session token.
+    pub fn ban(&self, player: &str, reason: &str) {
+        if player == HOST_ID {
+            return;
+        }
+        let token = self.inner.conns.borrow().iter()
+            .find(|(_, conn)| conn.peer() == player)
+            .map(|(_, conn)| conn.metadata().token)
+            .filter(|token| !token.is_empty());
+        let mut banned = self.inner.banned.borrow_mut();
+        banned.insert(player.to_string());
+        banned.extend(token);
+        drop(banned);
+        self.kick(player, reason);
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.inner.transport
+    }
+
+    pub fn now(&self) -> u64 {
+        (self.inner.now)()
+    }
+
+    /// Reads the game.
+    pub fn with_game<R>(&self, f: impl FnOnce(&Game) -> R) -> R {
+        f(&self.inner.game.borrow())
+    }
+
+    /// Changes the game, given the current time,
+    /// and delivers the messages it produces.
+    pub fn update(&self, f: impl FnOnce(&mut Game, u64) -> Outbox) {
+        let now = self.now();
+        let outbox = f(&mut self.inner.game.borrow_mut(), now);
+        self.deliver(outbox);
+    }
+
+    /// Handles a message from the host's own player.
+    pub fn send(&self, message: Message) {
+        self.update(|game, now| game.handle(HOST_ID, message, now));
+    }
+
+    /// Advances the game clock, and closes the connections
+    /// of clients who had their chance to hang up.
+    pub fn tick(&self) {
+        self.update(|game, now| game.tick(now));
+
+        let now = self.now();
+        let mut due = Vec::new();
+        self.inner.closing.borrow_mut().retain(|(peer, at)| {
+            if *at > now {
+                return true;
+            }
+            due.push(peer.clone());
+            false
+        });
+        let conns = self.inner.conns.borrow().clone();
+        for (_, conn) in conns.iter().filter(|(_, conn)| due.contains(&conn.peer())) {
+            conn.close();
+        }
+    }
+
+    /// Sends messages to their recipients, handing the
+    /// ones for everyone to the host's own player too.
+    /// Clients only get messages of features they support.
+    fn deliver(&self, outbox: Outbox) {
+        for (recipient, message) in outbox {
+            match recipient {
+                Recipient::All => {
+                    let conns = self.inner.conns.borrow();
+                    let supported = conns.iter().map(|(_, conn)| conn).filter(|conn| self.supports(&conn.peer(), &message));
+                    Connection::broadcast(supported, &message);
+                    drop(conns);
+                    (self.inner.local)(message);
+                },
+                Recipient::Player(id) if id == HOST_ID => (self.inner.local)(message),
+                Recipient::Player(id) => {
+                    let handshake = matches!(message, Message::Welcome(_) | Message::Pending(_) | Message::Error(_));
+                    if let Some((_, conn)) = self.inner.conns.borrow().iter().find(|(_, conn)| conn.peer() == id) {
+                        if handshake || self.supports(&id, &message) {
+                            conn.send(&message);
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    /// Whether the client `peer` has said hello and
+    /// supports the feature `message` belongs to.
+    fn supports(&self, peer: &str, message: &Message) -> bool {
+        self.inner.welcomed.borrow().get(peer).is_some_and(|welcome| {
+            message.feature().is_none_or(|feature| welcome.features.iter().any(|theirs| theirs == feature))
+        })
+    }
+
+    /// Stops hosting, closing every connection.
+    pub fn destroy(&self) {
+        self.inner.transport.destroy();
+    }
+}
+
+fn upgrade<T: Transport>(weak: &Weak<Inner<T>>) -> Option<Host<T>> {
+    weak.upgrade().map(|inner| Host { inner })
+}
@@ -0,0 +1,21 @@
+//! Game rules for Ripoff Robots: boards, robot movement,
+//! the solver, and running games over any transport.
+//!
+//! This crate has no browser dependencies; the Leptos UI
+//! in `main.rs` is built with the `web` feature.
+#![cfg_attr(test, feature(test))]
+
+pub mod board;
+pub mod clock;
+pub mod engine;
+pub mod game;
+pub mod host;
+pub mod link;
+pub mod net;
+pub mod profile;
+pub mod quadrant;
+pub mod rand;
+pub mod relay;
+pub mod room;
+pub mod solver;
+pub mod transport;
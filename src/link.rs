@@ -0,0 +1,331 @@
+//! A client's connection to the host, kept up as hosts come and go.
+//!
+//! The link says hello once a connection opens and waits for the
+//! host to answer. A host that drops is tried again, in case it was
+//! only a blip, and one that can't be reached is replaced: the room
+//! elects a player, who either takes over hosting or is connected to.
+//! A successor can be slow to notice it's needed, so one that opens
+//! connections but doesn't answer is tried a few times before the
+//! room elects another.
+
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+
+use crate::board::Board;
+use crate::game::{Game, HOST_ID};
+use crate::host::{Admission, Host};
+use crate::net::{self, Message, Metadata};
+use crate::rand::Rng;
+use crate::room::Room;
+use crate::transport::{Connection, Transport};
+
+/// How long to wait before connecting again, in milliseconds,
+/// giving a newly elected host time to start listening.
+pub const MIGRATION_DELAY: u64 = 1_000;
+
+/// How long a connection may take to open before
+/// giving up on it, in milliseconds, as PeerJS may never say.
+pub const CONNECT_TIMEOUT: u64 = 10_000;
+
+/// How long the host has to answer once a
+/// connection opens, in milliseconds.
+pub const WELCOME_TIMEOUT: u64 = 5_000;
+
+/// How many connections in a row a host may leave unanswered
+/// before another is elected. Enough to wait for a successor
+/// that only noticed the old host was gone when its own
+/// connection timed out.
+pub const SILENT_TRIES: u32 = 3;
+
+/// Runs a callback after the given number of milliseconds.
+pub type Schedule = Rc<dyn Fn(u64, Box<dyn FnOnce()>)>;
+
+/// What the link tells whoever is showing the room.
+#[allow(clippy::large_enum_variant)]
+pub enum Event<T: Transport> {
+    /// A connection to the host opened.
+    Open,
+
+    /// The host let us in over a new connection, and
+    /// is about to send everything again after its welcome.
+    Welcomed,
+
+    /// A message from the host.
+    Message(Message),
+
+    /// This peer took over as host, and the link is done.
+    Hosting(Host<T>),
+
+    /// The link is done: the host turned us away, saying
+    /// why, or there was nobody left to host.
+    Left(Option<String>),
+}
+
+struct State<T: Transport> {
+    /// The peer hosting, or that we hope is.
+    host: String,
+    conn: Option<T::Connection>,
+
+    /// Counts connections, so those given up on are ignored.
+    attempt: u64,
+    opened: bool,
+
+    /// Whether the host said anything over this connection.
+    heard: bool,
+    welcomed: bool,
+
+    /// How many connections in a row `host` left unanswered.
+    silent: u32,
+
+    /// Who to elect from, and what to resume, if the host goes.
+    room: Room,
+    board: Option<Board>,
+}
+
+struct Inner<T: Transport> {
+    transport: T,
+    metadata: Metadata,
+    now: Rc<dyn Fn() -> u64>,
+    schedule: Schedule,
+    on_event: Rc<dyn Fn(Event<T>)>,
+    state: RefCell<State<T>>,
+}
+
+/// A client's end of a game.
+pub struct Link<T: Transport> {
+    inner: Rc<Inner<T>>,
+}
+
+impl<T: Transport> Clone for Link<T> {
+    fn clone(&self) -> Self {
+        Link { inner: self.inner.clone() }
+    }
+}
+
+impl<T: Transport> Link<T> {
+    /// Connects to the peer `host`, introducing ourselves with
+    /// `metadata`. `now` is our best guess at the host's clock, and
+    /// is the game clock if we take over. Timers go to `schedule`.
+    pub fn connect(transport: T, host: &str, metadata: Metadata, now: impl Fn() -> u64 + 'static,
+        schedule: Schedule, on_event: impl Fn(Event<T>) + 'static) -> Self {
+        let room = Room::new(&transport.id());
+        let link = Link {
+            inner: Rc::new(Inner {
+                transport,
+                metadata,
+                now: Rc::new(now),
+                schedule,
+                on_event: Rc::new(on_event),
+                state: RefCell::new(State {
+                    host: host.to_string(),
+                    conn: None,
+                    attempt: 0,
+                    opened: false,
+                    heard: false,
+                    welcomed: false,
+                    silent: 0,
+                    room,
+                    board: None,
+                }),
+            }),
+        };
+        link.dial(host);
+        link
+    }
+
+    /// The peer hosting, or that we hope is.
+    pub fn host(&self) -> String {
+        self.inner.state.borrow().host.clone()
+    }
+
+    pub fn send(&self, message: &Message) {
+        let conn = self.inner.state.borrow().conn.clone();
+        if let Some(conn) = conn {
+            conn.send(message);
+        }
+    }
+
+    /// Leaves on purpose. Nothing more is heard from the link.
+    pub fn hang_up(&self) {
+        let conn = {
+            let mut state = self.inner.state.borrow_mut();
+            state.attempt += 1;
+            state.conn.take()
+        };
+        if let Some(conn) = conn {
+            conn.close();
+        }
+    }
+
+    fn dial(&self, host: &str) {
+        let conn = self.inner.transport.connect(host, &self.inner.metadata);
+        let attempt = {
+            let mut state = self.inner.state.borrow_mut();
+            if state.host != host {
+                state.host = host.to_string();
+                state.silent = 0;
+            }
+            state.attempt += 1;
+            state.conn = Some(conn.clone());
+            state.opened = false;
+            state.heard = false;
+            state.welcomed = false;
+            state.attempt
+        };
+
+        let weak = Rc::downgrade(&self.inner);
+        conn.on_open(move || {
+            if let Some(link) = current(&weak, attempt) {
+                link.opened(attempt);
+            }
+        });
+        let weak = Rc::downgrade(&self.inner);
+        conn.on_data(move |message| {
+            if let Some(link) = current(&weak, attempt) {
+                link.receive(message);
+            }
+        });
+        let weak = Rc::downgrade(&self.inner);
+        conn.on_close(move || {
+            if let Some(link) = current(&weak, attempt) {
+                link.lost();
+            }
+        });
+        self.after(CONNECT_TIMEOUT, attempt, |link| {
+            if !link.inner.state.borrow().opened {
+                link.lost();
+            }
+        });
+    }
+
+    fn opened(&self, attempt: u64) {
+        self.inner.state.borrow_mut().opened = true;
+        self.send(&Message::Hello(net::HelloMessage::current()));
+        self.emit(Event::Open);
+        self.after(WELCOME_TIMEOUT, attempt, |link| {
+            if !link.inner.state.borrow().heard {
+                link.lost();
+            }
+        });
+    }
+
+    /// Handles a message from the host. An error before the
+    /// welcome, or being kicked, means we can't play here.
+    fn receive(&self, message: Message) {
+        let (me, welcomed) = {
+            let mut state = self.inner.state.borrow_mut();
+            state.heard = true;
+            state.silent = 0;
+            (state.room.me.clone(), state.welcomed)
+        };
+        match &message {
+            Message::Error(msg) if !welcomed => return self.refused(msg.reason.clone()),
+            Message::PlayerLeave(net::PlayerLeaveMessage { id, reason: Some(reason) }) if *id == me =>
+                return self.refused(reason.clone()),
+            Message::Welcome(_) if !welcomed => {
+                let mut state = self.inner.state.borrow_mut();
+                state.welcomed = true;
+                state.room = Room::new(&me);
+                drop(state);
+                self.emit(Event::Welcomed);
+            },
+            Message::BoardState(msg) => self.inner.state.borrow_mut().board = Some(msg.board.clone()),
+            _ => {},
+        }
+        let now = (self.inner.now)();
+        self.inner.state.borrow_mut().room.apply(&message, now);
+        self.emit(Event::Message(message));
+    }
+
+    fn refused(&self, reason: String) {
+        self.hang_up();
+        self.emit(Event::Left(Some(reason)));
+    }
+
+    /// Handles losing the connection to the host, or never getting
+    /// an answer from it. A host we heard from is tried again, as
+    /// is one that opened but stayed quiet, for a while. Otherwise
+    /// the host is given up on.
+    fn lost(&self) {
+        let (host, retry, conn) = {
+            let mut state = self.inner.state.borrow_mut();
+            state.attempt += 1;
+            let retry = state.heard || (state.opened && {
+                state.silent += 1;
+                state.silent < SILENT_TRIES
+            });
+            (state.host.clone(), retry, state.conn.take())
+        };
+        if let Some(conn) = conn {
+            conn.close();
+        }
+        match retry {
+            true => self.redial(host),
+            false => self.migrate(&host),
+        }
+    }
+
+    /// Connects to `host` once `MIGRATION_DELAY` is over,
+    /// unless we hang up in the meantime.
+    fn redial(&self, host: String) {
+        let attempt = self.inner.state.borrow().attempt;
+        self.after(MIGRATION_DELAY, attempt, move |link| link.dial(&host));
+    }
+
+    /// Carries on without the peer `gone`, who was hosting or was
+    /// elected to. The next peer in line hosts the game where it
+    /// left off, and the rest connect to it.
+    fn migrate(&self, gone: &str) {
+        let (successor, me) = {
+            let mut state = self.inner.state.borrow_mut();
+            (state.room.elect(gone), state.room.me.clone())
+        };
+        match successor {
+            None => self.emit(Event::Left(None)),
+            Some(successor) if successor == me => self.take_over(),
+            Some(successor) => self.redial(successor),
+        }
+    }
+
+    fn take_over(&self) {
+        let state = self.inner.state.borrow();
+        let Some(board) = state.board.clone() else {
+            drop(state);
+            return self.emit(Event::Left(None));
+        };
+        let game = Game::resume(board, &state.room, Rng::from_entropy());
+        // the old host said whether it approved joins, and
+        // whoever joined with the password can pass it on
+        let approve = state.room.welcome.as_ref().is_some_and(|welcome| welcome.approve);
+        drop(state);
+
+        let now = self.inner.now.clone();
+        let on_event = self.inner.on_event.clone();
+        let host = Host::new(self.inner.transport.clone(), game, move || now(), move |message| on_event(Event::Message(message)));
+        let password = Some(self.inner.metadata.password.clone()).filter(|password| !password.is_empty());
+        host.set_admission(Admission { password, approve });
+        self.emit(Event::Hosting(host.clone()));
+        host.update(|game, _| game.join(HOST_ID, &self.inner.metadata.name));
+    }
+
+    /// Runs `f` after `delay`, if `attempt` is still the latest.
+    fn after(&self, delay: u64, attempt: u64, f: impl FnOnce(&Link<T>) + 'static) {
+        let weak = Rc::downgrade(&self.inner);
+        (self.inner.schedule)(delay, Box::new(move || {
+            if let Some(link) = current(&weak, attempt) {
+                f(&link);
+            }
+        }));
+    }
+
+    fn emit(&self, event: Event<T>) {
+        (self.inner.on_event)(event);
+    }
+}
+
+/// The link, if `attempt` is still its latest.
+fn current<T: Transport>(weak: &Weak<Inner<T>>, attempt: u64) -> Option<Link<T>> {
+    let inner = weak.upgrade()?;
+    let latest = inner.state.borrow().attempt == attempt;
+    latest.then_some(Link { inner })
+}
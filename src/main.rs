@@ -1,22 +1,33 @@
 #![feature(extract_if)]
 #![feature(extern_types)]
-use std::{collections::{HashSet, BinaryHeap, HashMap}, cmp::Ordering};
+use std::{collections::HashSet, rc::Rc, time::Duration};
 
 use js_sys::{Number, Reflect};
 use leptos::*;
 use leptos::ev::KeyboardEvent;
 use wasm_bindgen::JsValue;
 use wasm_bindgen::prelude::Closure;
-mod rand;
 mod utils;
+mod backend;
 mod peer;
-mod board;
-use board::{Board, RobotPositions, Direction};
-use web_sys::console;
-mod net;
+mod ws;
+use robots::{board, clock, game, host, link, net, profile, rand, room, solver, transport};
+use board::{Board, RobotPositions, Direction, GenerateOptions, RobotPlacement, Layout};
+use game::{Game, Phase, HOST_ID};
+use clock::Clock;
+use host::{Admission, Host};
+use link::Link;
+use profile::{KeyBindings, Palette, Profile};
+use room::Room;
+use transport::Transport;
+use backend::AnyTransport;
 
 #[component]
-pub fn BoardWidget(cx: Scope, board: ReadSignal<Board>, positions: Option<RwSignal<RobotPositions>>, moves: RwSignal<Vec<(usize, Direction)>>) -> impl IntoView {
+pub fn BoardWidget(cx: Scope, board: ReadSignal<Board>, target: ReadSignal<Option<usize>>, positions: Option<RwSignal<RobotPositions>>, moves: RwSignal<Vec<(usize, Direction)>>,
+    /// Whether the robots are being moved by
+    /// someone else, e.g. while watching a demo.
+    locked: Signal<bool>,
+    keys: Signal<KeyBindings>) -> impl IntoView {
     // invariant: if set_position is None, moves is empty
     let (positions, set_positions) = match positions {
         None => (Signal::derive(cx, move || board.get().initial_positions), None),
@@ -26,15 +37,11 @@ pub fn BoardWidget(cx: Scope, board: ReadSignal<Board>, positions: Option<RwSign
     let keydown = move |robot, evt: KeyboardEvent| {
         let positions = positions.get();
 
-        let dir = match evt.code().as_str() {
-            "ArrowDown" => Direction::Down,
-            "ArrowUp" => Direction::Up,
-            "ArrowRight" => Direction::Right,
-            "ArrowLeft" => Direction::Left,
-            _ => {return}
+        let Some(dir) = keys.with_untracked(|keys| keys.direction(&evt.code())) else {
+            return
         };
 
-        let new_positions = board.get().move_robot(positions, robot, dir);
+        let new_positions = board.with(|board| board.move_robot(positions, robot, dir));
         if new_positions != positions {
             set_positions.unwrap().set(new_positions);
             moves.update(|moves| moves.push((robot, dir)));
@@ -53,7 +60,7 @@ pub fn BoardWidget(cx: Scope, board: ReadSignal<Board>, positions: Option<RwSign
     view !{
         cx, 
         <div class="board" style={move || format!("width:{}px;height:{}px", 32 * board.get().width, 32 * board.get().height())}>
-            {move || if moves.get().len() != 0 {
+            {move || if moves.get().len() != 0 && !locked.get() {
                 Some(view!{ cx, <div class="refresh" on:click={move |_| {
                     moves.update(|v| v.clear());
                     set_positions.unwrap().set(board.get().initial_positions);
@@ -71,6 +78,37 @@ pub fn BoardWidget(cx: Scope, board: ReadSignal<Board>, positions: Option<RwSign
                     }
                 }/>
 
+            <For
+                each={move || board.get().targets.into_iter().enumerate().collect::<Vec<_>>()}
+                key={|&(i, t)| (i, t.tile)}
+                view=move |cx, (i, t)| {
+                    view! {
+                        cx,
+                        <div class={move || format!("target target-{} target-{} {}",
+                                t.robot.map(|r| r.to_string()).unwrap_or("any".into()),
+                                t.symbol.id(),
+                                if target.get() == Some(i) { "target-active" } else { "" })}
+                            style={
+                                let width = board.get().width;
+                                format!("top:{}px;left:{}px", 32 * (t.tile / width), 32 * (t.tile % width))}></div>
+                    }
+                }
+                />
+
+            <For
+                each={move || board.get().diagonals}
+                key={|&d| d}
+                view=move |cx, d| {
+                    view! {
+                        cx,
+                        <div class={format!("diagonal diagonal-{} diagonal-{}", d.robot, d.orientation.id())}
+                            style={
+                                let width = board.get().width;
+                                format!("top:{}px;left:{}px", 32 * (d.tile / width), 32 * (d.tile % width))}></div>
+                    }
+                }
+                />
+
             <For
                 each=move || 0..5
                 key=|&i| i
@@ -79,7 +117,7 @@ pub fn BoardWidget(cx: Scope, board: ReadSignal<Board>, positions: Option<RwSign
                         cx,
                         <div class={move || format!("robot robot-{}", i)}
                             tabIndex="-1"
-                            on:keydown={move |evt| if set_positions.is_some() { keydown(i, evt) }}
+                            on:keydown={move |evt| if set_positions.is_some() && !locked.get_untracked() { keydown(i, evt) }}
                             style={move || {
                                 let width = board.get().width;
                                 let pos = positions.get()[i];
@@ -139,97 +177,578 @@ pub fn MoveList(cx: Scope, moves: ReadSignal<Vec<(usize, Direction)>>) -> impl I
     }
 }
 
+/// Longest solution the solver looks for.
+const SOLVER_MAX_MOVES: usize = 10;
+
+/// How many positions the solver looks at before giving
+/// up, so a long search doesn't freeze the page for long.
+const SOLVER_MAX_NODES: usize = 200_000;
+
+/// Solves `puzzle`, a board and target, on request.
+/// Hidden while there's no puzzle it may solve.
+#[component]
+pub fn SolutionWidget(cx: Scope, puzzle: Signal<Option<(Board, Option<usize>)>>) -> impl IntoView {
+    let solution = create_rw_signal(cx, Vec::new());
+    let status = create_rw_signal(cx, String::new());
+
+    // clear the solution when the puzzle changes
+    create_effect(cx, move |_| {
+        puzzle.track();
+        solution.set(Vec::new());
+        status.set(String::new());
+    });
+
+    let solve = move |_| {
+        let Some((board, target)) = puzzle.get() else {
+            return;
+        };
+        let Some(target) = target.and_then(|i| board.targets.get(i).copied()) else {
+            status.set("No target".into());
+            return;
+        };
+
+        status.set("Solving...".into());
+        solution.set(Vec::new());
+        // let the page show that before searching
+        set_timeout(move || {
+            match solver::solve_within(&board, board.initial_positions, &target, SOLVER_MAX_MOVES, SOLVER_MAX_NODES) {
+                Ok(moves) => {
+                    status.set(format!("Optimal: {} moves", moves.len()));
+                    solution.set(moves);
+                },
+                Err(solver::Unsolved::TooLong) => status.set(format!("No solution in {} moves", SOLVER_MAX_MOVES)),
+                Err(solver::Unsolved::GaveUp) => status.set("Too hard to solve here".into()),
+            }
+        }, Duration::ZERO);
+    };
+
+    view! { cx,
+        <div class="solution" prop:hidden={move || puzzle.with(Option::is_none)}>
+            <button on:click={solve}>"Solve"</button>
+            <span class="solution-status">{move || status.get()}</span>
+            <MoveList moves={solution.read_only()} />
+        </div>
+    }
+}
+
+/// Lets the host pick the board size and
+/// layout and generate new boards.
+#[component]
+pub fn BoardSettings<F>(cx: Scope, positions: ReadSignal<RobotPositions>, on_board: F) -> impl IntoView
+where
+    F: Fn(Board) + 'static
+{
+    let width = create_rw_signal(cx, 16);
+    let height = create_rw_signal(cx, 16);
+    let quadrants = create_rw_signal(cx, true);
+    let center = create_rw_signal(cx, true);
+    let diagonals = create_rw_signal(cx, 0);
+    let keep_robots = create_rw_signal(cx, false);
+    let error = create_rw_signal(cx, String::new());
+
+    let randomize_board = move |_| {
+        let options = GenerateOptions {
+            width: width.get(),
+            height: height.get(),
+            layout: if quadrants.get() { Layout::Quadrants } else { Layout::Scattered },
+            center: center.get(),
+            diagonals: diagonals.get(),
+            robots: if keep_robots.get() { RobotPlacement::Keep(positions.get()) } else { RobotPlacement::Random },
+        };
+        match Board::generate(&options, &mut rand::Rng::from_entropy()) {
+            Ok(new_board) => {
+                error.set(String::new());
+                on_board(new_board);
+            },
+            Err(err) => error.set(err.to_string())
+        }
+    };
+
+    view! { cx,
+        <div class="board-settings">
+            <input type="number" min={board::MIN_SIZE} max={board::MAX_SIZE} prop:value={move || width.get()}
+                on:input={move |ev| width.set(event_target_value(&ev).parse().unwrap_or(0))} />
+            "x"
+            <input type="number" min={board::MIN_SIZE} max={board::MAX_SIZE} prop:value={move || height.get()}
+                on:input={move |ev| height.set(event_target_value(&ev).parse().unwrap_or(0))} />
+            <label>
+                <input type="checkbox" prop:checked={move || quadrants.get()}
+                    on:change={move |ev| quadrants.set(event_target_checked(&ev))} />
+                "Classic quadrants"
+            </label>
+            <label>
+                <input type="checkbox" prop:checked={move || center.get()}
+                    on:change={move |ev| center.set(event_target_checked(&ev))} />
+                "Center block"
+            </label>
+            <label>
+                <input type="number" min="0" prop:value={move || diagonals.get()}
+                    on:input={move |ev| diagonals.set(event_target_value(&ev).parse().unwrap_or(0))} />
+                "Diagonals"
+            </label>
+            <label>
+                <input type="checkbox" prop:checked={move || keep_robots.get()}
+                    on:change={move |ev| keep_robots.set(event_target_checked(&ev))} />
+                "Keep robots"
+            </label>
+            <button on:click={randomize_board}>"New Board"</button>
+            <div class="board-settings-error">{move || error.get()}</div>
+        </div>
+    }
+}
+
 #[derive(Clone)]
 pub enum NetworkState {
     None,
-    Client(peer::DataConnection),
-    Server { peer: peer::Peer, conns: Vec<peer::DataConnection>, initialized: bool }
+    /// Connected to the host, or finding
+    /// another if the host goes away.
+    Client(Link<AnyTransport>),
+    Server(Host<AnyTransport>),
 }
 
-#[derive(Clone, PartialEq, Eq)]
-pub struct Bid {
-    timestamp: u32,
-    bid: u32,
-    name: String,
+/// How often clients measure the host's clock.
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where the profile is kept in localStorage.
+const PROFILE_KEY: &str = "ripoff-robots-profile";
+
+/// Where a profile that couldn't be loaded is kept,
+/// rather than lost when a new one is saved over it.
+const PROFILE_BACKUP_KEY: &str = "ripoff-robots-profile-unreadable";
+
+/// Everything the network handlers need,
+/// bundled so it can be copied into closures.
+#[derive(Clone, Copy)]
+pub struct Session {
+    network: RwSignal<NetworkState>,
+    room: RwSignal<Room>,
+    board: RwSignal<Board>,
+    target: RwSignal<Option<usize>>,
+
+    /// The robots on the board and the
+    /// moves that brought them there.
+    positions: RwSignal<RobotPositions>,
+    moves: RwSignal<Vec<(usize, Direction)>>,
+
+    /// Our estimate of the host's clock. The host's own is exact.
+    clock: StoredValue<Clock>,
+
+    /// Current host time in milliseconds, updated by a timer.
+    now: RwSignal<u64>,
+
+    /// What we tell the host when connecting, kept for reconnecting.
+    /// Its token lets us reclaim our score after losing the connection.
+    metadata: StoredValue<net::Metadata>,
+
+    /// Why we couldn't connect, or were turned away.
+    error: RwSignal<String>,
+
+    /// Saved to localStorage whenever it changes.
+    profile: RwSignal<Profile>,
+
+    /// The board and target of the last round to end,
+    /// whose solution can be shown without giving it away.
+    last_round: RwSignal<Option<(Board, Option<usize>)>>,
 }
 
-impl PartialOrd for Bid {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(if self.bid < other.bid { 
-            Ordering::Less
-        } else if self.bid > other.bid {
-            Ordering::Greater
-        } else if self.timestamp < other.timestamp {
-            Ordering::Less
-        } else if self.timestamp > other.timestamp {
-            Ordering::Greater
-        } else {
-            Ordering::Equal
-        })
+impl Session {
+    /// Updates the board and the local view
+    /// of the room with a message from the host.
+    fn apply(self, message: net::Message) {
+        match &message {
+            net::Message::BoardState(state) => {
+                self.board.set(state.board.clone());
+                self.target.set(state.target);
+            },
+            net::Message::EndRound(_) => {
+                // the next round's board state is still to come
+                self.last_round.set(Some((self.board.get_untracked(), self.target.get_untracked())));
+            },
+            net::Message::StartEval(_) => {
+                // the demonstration starts from the beginning
+                self.reset_robots();
+            },
+            net::Message::DemoMove(msg) => {
+                if !self.is_me(&msg.player) {
+                    let positions = self.board.with_untracked(|board| board.move_robot(self.positions.get_untracked(), msg.robot, msg.direction));
+                    self.positions.set(positions);
+                    self.moves.update(|moves| moves.push((msg.robot, msg.direction)));
+                }
+            },
+            net::Message::DemoReset(msg) => {
+                if !self.is_me(&msg.player) {
+                    self.reset_robots();
+                }
+            },
+            net::Message::Pong(msg) => {
+                let received = js_sys::Date::now() as u64;
+                self.clock.update_value(|clock| clock.sample(msg.sent, msg.host_time, received));
+            },
+            net::Message::Error(msg) => error!("rejected by host: {}", msg.reason),
+            _ => {},
+        }
+        let now = self.host_time();
+        self.room.update(|room| room.apply(&message, now));
+    }
+
+    fn host_time(self) -> u64 {
+        self.clock.with_value(|clock| clock.host_time(js_sys::Date::now() as u64))
+    }
+
+    fn ping(self) {
+        if let NetworkState::Client(link) = self.network.get_untracked() {
+            link.send(&net::Message::Ping(net::PingMessage { sent: js_sys::Date::now() as u64 }));
+        }
+    }
+
+    fn is_me(self, player: &str) -> bool {
+        self.room.with_untracked(|room| room.me == player)
+    }
+
+    fn reset_robots(self) {
+        self.positions.set(self.board.with_untracked(|board| board.initial_positions));
+        self.moves.update(|moves| moves.clear());
+    }
+
+    /// Whether this peer is demonstrating their solution.
+    fn demonstrating(self) -> bool {
+        self.room.with(|room| room.demonstrating())
+    }
+
+    /// Whether someone else is demonstrating their solution.
+    fn spectating(self) -> bool {
+        self.room.with(|room| room.spectating())
+    }
+
+    /// Sends a message as whoever this peer is: to the
+    /// host if a client, or straight to the game if hosting.
+    fn send(self, message: net::Message) {
+        match self.network.get_untracked() {
+            NetworkState::Client(link) => link.send(&message),
+            NetworkState::Server(host) => host.send(message),
+            NetworkState::None => {},
+        }
+    }
+
+    /// Runs `f` on the game if hosting and delivers its messages.
+    fn host(self, f: impl FnOnce(&mut Game) -> game::Outbox) {
+        if let NetworkState::Server(host) = self.network.get_untracked() {
+            host.update(|game, _| f(game));
+        }
+    }
+
+    /// Joins the room hosted by `host_id` as a client.
+    fn join(self, transport: AnyTransport, host_id: &str) {
+        self.room.set(Room::new(&transport.id()));
+        let schedule: link::Schedule = Rc::new(|delay, f| set_timeout(f, Duration::from_millis(delay)));
+        let link = Link::connect(transport, host_id, self.metadata.get_value(),
+            move || self.host_time(), schedule, move |event| self.follow(event));
+        self.network.set(NetworkState::Client(link));
+    }
+
+    /// Keeps up with the link to the host as hosts come and go.
+    fn follow(self, event: link::Event<AnyTransport>) {
+        match event {
+            link::Event::Open => {
+                log!("connection opened to host!");
+                self.ping();
+            },
+            // the host sends everything again after reconnecting
+            link::Event::Welcomed => self.room.update(|room| *room = Room::new(&room.me)),
+            link::Event::Message(message) => self.apply(message),
+            link::Event::Hosting(host) => {
+                log!("taking over as host");
+                self.room.set(Room::new(HOST_ID));
+                self.network.set(NetworkState::Server(host));
+            },
+            link::Event::Left(reason) => {
+                self.network.set(NetworkState::None);
+                if let Some(reason) = reason {
+                    self.error.set(reason);
+                }
+            },
+        }
+    }
+
+    /// Leaves the host on purpose.
+    fn hang_up(self) {
+        if let NetworkState::Client(link) = self.network.get_untracked() {
+            self.network.set(NetworkState::None);
+            link.hang_up();
+        }
+    }
+
+    fn bid(self, bid: u8) {
+        let timestamp = Some(self.host_time());
+        self.send(net::Message::MakeBid(net::MakeBidMessage { bid, timestamp }));
+    }
+
+    /// Submits the moves on the board as this peer's solution.
+    fn submit(self) {
+        let moves = self.moves.get_untracked();
+        self.send(net::Message::SubmitMoves(net::SubmitMovesMessage { moves }));
     }
 }
-impl Ord for Bid {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
+
+/// The current round: countdown, bids and
+/// who is demonstrating their solution.
+#[component]
+pub fn Round(cx: Scope, session: Session) -> impl IntoView {
+    let room = session.room;
+    let bid = create_rw_signal(cx, String::new());
+    let name = move |id: &str| room.with(|room| room.players.get(id).cloned().unwrap_or_else(|| id.to_string()));
+
+    let make_bid = move |_| {
+        if let Ok(value) = bid.get().parse::<u8>() {
+            session.bid(value);
+            bid.set(String::new());
+        }
+    };
+
+    let verdict = move || room.get().verdict.map(|verdict| format!("{} {} in {} moves",
+        name(&verdict.player),
+        if verdict.success { "solved it" } else { "failed" },
+        verdict.moves.len()));
+
+    let status = move || match room.get().phase {
+        Phase::Idle => "Waiting for the next round".to_string(),
+        Phase::Revealed => "Find a solution and bid".to_string(),
+        Phase::Bidding { end_time } => {
+            let left = end_time.saturating_sub(session.now.get());
+            format!("Bidding closes in {}s", (left + 999) / 1000)
+        },
+        Phase::Evaluating { player, bid } => format!("{} shows {} moves", name(&player), bid),
+    };
+
+    view! { cx,
+        <div class="round">
+            <div class="round-status">{status}</div>
+            <div class="round-verdict">{verdict}</div>
+            <div class="round-error">{move || room.get().error}</div>
+            {move || session.demonstrating().then(|| view! { cx,
+                <button on:click={move |_| session.submit()}>"Submit moves"</button>
+            })}
+            {move || (room.with(|room| room.playing()) && matches!(room.get().phase, Phase::Revealed | Phase::Bidding { .. })).then(|| view! { cx,
+                <div class="round-bid">
+                    <input type="number" min="1" placeholder="moves" prop:value={move || bid.get()}
+                        on:input={move |ev| bid.set(event_target_value(&ev))} />
+                    <button on:click={make_bid}>"Bid"</button>
+                </div>
+            })}
+            <div class="round-bids">
+                <For each={move || {
+                        let mut bids = room.get().bids.into_values().collect::<Vec<_>>();
+                        bids.sort();
+                        bids
+                    }}
+                    key={|bid| (bid.player.clone(), bid.bid)}
+                    view=move |cx, bid| {
+                        view! { cx,
+                            <div class="round-bid-entry">
+                                <span class="network-player-name">{name(&bid.player)}</span>
+                                <span class="round-bid-moves">{bid.bid}</span>
+                            </div>
+                        }
+                    }
+                    />
+            </div>
+        </div>
     }
 }
 
-#[derive(Clone, Default)]
-pub struct RoomState {
-    players: HashMap<String, String>,
-    scores: HashMap<String, u32>,
-    bids: BinaryHeap<Bid>
+/// The room's chat, with notices of who came and
+/// went and how the rounds went between the lines.
+#[component]
+pub fn Chat(cx: Scope, session: Session) -> impl IntoView {
+    let room = session.room;
+    let text = create_rw_signal(cx, String::new());
+
+    let say = move || {
+        if !text.get_untracked().trim().is_empty() {
+            session.send(net::Message::Chat(net::ChatMessage { text: text.get_untracked(), ..Default::default() }));
+            text.set(String::new());
+        }
+    };
+
+    view! { cx,
+        <div class="chat">
+            <div class="chat-lines">
+                {move || room.with(|room| room.chat.iter().map(|line| match &line.name {
+                    Some(name) => view! { cx,
+                        <div class="chat-line">
+                            <span class="network-player-name">{name.clone()}</span>
+                            <span class="chat-text">{line.text.clone()}</span>
+                        </div>
+                    },
+                    None => view! { cx,
+                        <div class="chat-line chat-notice">{line.text.clone()}</div>
+                    },
+                }).collect_view(cx))}
+            </div>
+            <div class="chat-input">
+                <input type="text" placeholder="Say something" maxlength={game::CHAT_LENGTH} prop:value={move || text.get()}
+                    on:input={move |ev| text.set(event_target_value(&ev))}
+                    on:keydown={move |ev| if ev.key() == "Enter" { say() }} />
+                <button on:click={move |_| say()}>"Send"</button>
+            </div>
+        </div>
+    }
 }
 
 #[component]
-pub fn Network(cx: Scope, state: RwSignal<NetworkState>, room_state: RwSignal<RoomState>, board: WriteSignal<Board>) -> impl IntoView {
-    // NOTE: Never directly set `state` to `None`
+pub fn Network(cx: Scope, session: Session, positions: ReadSignal<RobotPositions>) -> impl IntoView {
+    // NOTE: Never directly set `state` to `None`. Hosts end by
+    // destroying their transport, and clients by `Session::hang_up`;
+    // otherwise only the link clears it once no host is left.
+    let Session { network: state, room: room_state, board, target, error, .. } = session;
     let room_id = create_rw_signal(cx, String::new());
-    let name = create_rw_signal(cx, String::new());
-    let join = move |evt| {
+    let name = create_rw_signal(cx, session.profile.with_untracked(|profile| profile.name.clone()));
+    // a self-hosted relay server to use instead of PeerJS
+    let relay_url = create_rw_signal(cx, String::new());
+    // set by the host, and sent by joiners
+    let password = create_rw_signal(cx, String::new());
+    let approve = create_rw_signal(cx, false);
+    // clients waiting for the host to let them in
+    let pending = create_rw_signal(cx, Vec::<(String, net::Metadata)>::new());
+
+    create_effect(cx, move |_| {
+        pending.set(Vec::new());
+        if let NetworkState::Server(host) = state.get() {
+            // a host that took over may have kept approving joins
+            approve.set(host.admission().approve);
+            host.on_pending(move |waiting| pending.set(waiting));
+        }
+    });
+
+    // joins the room to play, or just to watch
+    let connect = move |spectator: bool| {
         log!("joining room {}", room_id.get());
+        error.set(String::new());
 
-        let id = format!("ripoff-robots-client-{:x}", rand::uniform(0, i32::MAX as usize));
-        let peer = peer::Peer::new(&id, &JsValue::NULL);
+        let id = format!("ripoff-robots-client-{:x}", rand::Rng::from_entropy().uniform(0, i32::MAX as usize));
+        let host_id = format!("ripoff-robots-{}", room_id.get());
+        session.metadata.update_value(|metadata| {
+            metadata.name = name.get();
+            metadata.spectator = spectator;
+            metadata.password = password.get();
+        });
 
-        let peer_clone = peer.clone();
-        peer.on("open", &Closure::<dyn Fn()>::new(move || {
-            let options = object!{
-                "metadata" => &object!{
-                    "name" => name.get()
-                }
-            };
-            let conn = peer_clone.connect(&format!("ripoff-robots-{}", room_id.get()), &options.into());
-    
-            state.set(NetworkState::Client (conn));
-        }).into_js_value());
+        if relay_url.get().is_empty() {
+            let peer = peer::Peer::new(&id, &JsValue::NULL);
+            let peer_clone = peer.clone();
+            peer.on("open", &Closure::<dyn Fn()>::new(move || {
+                session.join(AnyTransport::PeerJs(peer_clone.clone()), &host_id);
+            }).into_js_value());
+        } else {
+            // the relay queues frames until its socket opens
+            match ws::connect(&relay_url.get(), &host_id, &id) {
+                Ok(relay) => session.join(AnyTransport::Relay(relay), &host_id),
+                Err(err) => error.set(format!("can't reach relay: {:?}", err)),
+            }
+        }
     };
+    let join = move |_| connect(false);
+    let watch = move |_| connect(true);
 
     let host = move |evt| {
-        let id = format!("{:x}", rand::uniform(0, i32::MAX as usize));
-        let peer = peer::Peer::new(&format!("ripoff-robots-{}", &id), object!{}.as_ref());
-        state.set(NetworkState::Server { peer, conns: vec![], initialized: false });
-        room_state.update(|state| {
-            state.players.insert("host".into(), name.get());
+        error.set(String::new());
+        let id = format!("ripoff-robots-{:x}", rand::Rng::from_entropy().uniform(0, i32::MAX as usize));
+        let transport = if relay_url.get().is_empty() {
+            AnyTransport::PeerJs(peer::Peer::new(&id, object!{}.as_ref()))
+        } else {
+            match ws::connect(&relay_url.get(), &id, &id) {
+                Ok(relay) => AnyTransport::Relay(relay),
+                Err(err) => {
+                    error.set(format!("can't reach relay: {:?}", err));
+                    return;
+                },
+            }
+        };
+        let game = Game::new(board.get_untracked(), rand::Rng::from_entropy());
+        let host = Host::new(transport, game, move || session.host_time(), move |message| session.apply(message));
+        host.set_admission(Admission {
+            password: Some(password.get_untracked()).filter(|password| !password.is_empty()),
+            approve: approve.get_untracked(),
         });
+        room_state.set(Room::new(HOST_ID));
+        state.set(NetworkState::Server(host.clone()));
+        host.update(|game, _| game.join(HOST_ID, &name.get_untracked()));
     };
 
     let end_host = move |evt| {
         log!("destroying");
-        if let NetworkState::Server { peer, .. } = state.get() {
+        if let NetworkState::Server(host) = state.get() {
             log!("destroying");
-            peer.destroy();
+            host.destroy();
         }
     };
 
-    let end_client = move |evt| {
-        if let NetworkState::Client(conn) = state.get() {
-            conn.close();
+    let end_client = move |evt| session.hang_up();
+
+    let admit = move |id: String| if let NetworkState::Server(host) = state.get_untracked() {
+        host.admit(&id);
+    };
+    let reject = move |id: String| if let NetworkState::Server(host) = state.get_untracked() {
+        host.reject(&id, "The host didn't let you in.");
+    };
+    let kick = move |id: String| if let NetworkState::Server(host) = state.get_untracked() {
+        host.kick(&id, "The host kicked you.");
+    };
+    let ban = move |id: String| if let NetworkState::Server(host) = state.get_untracked() {
+        host.ban(&id, "The host banned you.");
+    };
+    let set_approve = move |ev| {
+        approve.set(event_target_checked(&ev));
+        if let NetworkState::Server(host) = state.get_untracked() {
+            host.set_admission(Admission { approve: approve.get_untracked(), ..host.admission() });
         }
     };
 
-    let randomize_board = move |evt| {
-        board.set(Board::generate(16, 16));
+    let waiting = move || (!pending.get().is_empty()).then(|| view! { cx,
+        <div class="network-pending">
+            <h3>"Waiting to join"</h3>
+            <For each={move || pending.get()}
+                key=|(id, _)| id.clone()
+                view=move |cx, (id, metadata)| {
+                    let rejected = id.clone();
+                    view! { cx,
+                        <div class="network-pending-entry">
+                            <span class="network-player-name">{metadata.name}{metadata.spectator.then_some(" (watching)")}</span>
+                            <button on:click={move |_| admit(id.clone())}>"Accept"</button>
+                            <button on:click={move |_| reject(rejected.clone())}>"Reject"</button>
+                        </div>
+                    }
+                }
+                />
+        </div>
+    });
+
+    let start_round = move |_| session.host(|game| game.start_round());
+    let skip = move |_| session.host(|game| game.resolve(false));
+
+    let players = move || view! { cx,
+        <div class="network-players">
+            <h3>"Players"</h3>
+            <For each={move || room_state.get().players.iter().map(|(id, name)| (id.to_owned(), name.to_owned())).collect::<Vec<_>>()}
+                key=|(id,_name)| id.to_string()
+                view=move |cx, (id, name)| {
+                    // only the host can remove players, and not itself
+                    let removable = id != HOST_ID && matches!(state.get_untracked(), NetworkState::Server(_));
+                    let (kicked, banned) = (id.clone(), id.clone());
+                    view!{
+                        cx, 
+                        <div class="network-player">
+                            <span class="network-player-name">{name}</span>
+                            <span class="network-player-score">{move || room_state.get().scores.get(&id).map(|x|*x).unwrap_or(0)}</span>
+                            {removable.then(|| view! { cx,
+                                <button on:click={move |_| kick(kicked.clone())}>"Kick"</button>
+                                <button on:click={move |_| ban(banned.clone())}>"Ban"</button>
+                            })}
+                        </div>
+                    }
+                }
+                />
+        </div>
     };
 
     view! {
@@ -237,68 +756,92 @@ pub fn Network(cx: Scope, state: RwSignal<NetworkState>, room_state: RwSignal<Ro
         <div class="network-state">
             {move || match state.get() {
                 NetworkState::None => {
+                    let new_target = move |_| {
+                        let count = board.get().targets.len();
+                        target.set(Some(rand::Rng::from_entropy().uniform(0, count)));
+                    };
                     view! {
                         cx, 
                         <div class="network-state-none">
                             <input type="text" placeholder="name" prop:value={name}
-                                on:input={move |ev| name.set(event_target_value(&ev))} />
+                                on:input={move |ev| {
+                                    name.set(event_target_value(&ev));
+                                    session.profile.update(|profile| profile.name = name.get_untracked());
+                                }} />
+                            <input type="password" placeholder="Room password (optional)"
+                                prop:value={move || password.get()}
+                                on:input={move |ev| password.set(event_target_value(&ev))} />
                             <hr />
                             <button on:click={host} class="network-button-host">"Host"</button>
+                            <label>
+                                <input type="checkbox" prop:checked={move || approve.get()}
+                                    on:change={move |ev| approve.set(event_target_checked(&ev))} />
+                                "Approve joins"
+                            </label>
                             <hr />
                             <input type="text" placeholder="Room ID" 
                                 prop:value={room_id}
                                 on:input={move |ev| room_id.set(event_target_value(&ev))} />
                             <button on:click={join} class="network-button-join">"Join"</button>
+                            <button on:click={watch} class="network-button-watch">"Watch"</button>
+                            <input type="text" placeholder="Relay server (optional)"
+                                prop:value={move || relay_url.get()}
+                                on:input={move |ev| relay_url.set(event_target_value(&ev))} />
+                            <div class="network-error">{move || error.get()}</div>
                             <hr />
-                            <button on:click={randomize_board}>"New Board"</button>
+                            <BoardSettings positions={positions} on_board={move |new_board| {
+                                board.set(new_board);
+                                target.set(None);
+                            }} />
+                            <button on:click={new_target}>"New Target"</button>
                         </div>
                     }.into_any()
                 },
-                NetworkState::Server { peer, conns, .. } => {
+                NetworkState::Server(host) => {
                     view! {
                         cx,
                         <div class="network-state-host">
-                            <div class="network-host-id">"Room ID: " {format!("{}", &peer.id()["ripoff-robots-".len()..])}</div>
-                            <div class="network-players">
-                                <h3>"Players"</h3>
-                                <For each={move || room_state.get().players.iter().map(|(id, name)| (id.to_owned(), name.to_owned())).collect::<Vec<_>>()}
-                                    key=|(id,_name)| id.to_string()
-                                    view=move |cx, (id, name)| {
-                                        view!{
-                                            cx, 
-                                            <div class="network-player">
-                                                <span class="network-player-name">{name}</span>
-                                                <span class="network-player-score">{move || room_state.get().scores.get(&id).map(|x|*x).unwrap_or(0)}</span>
-                                            </div>
-                                        }
-                                    }
-                                    />
+                            <div class="network-host-id">"Room ID: " {format!("{}", &host.transport().id()["ripoff-robots-".len()..])}</div>
+                            <label>
+                                <input type="checkbox" prop:checked={move || approve.get()} on:change={set_approve} />
+                                "Approve joins"
+                            </label>
+                            {waiting}
+                            {players}
+                            <Round session={session} />
+                            <Chat session={session} />
+                            <div class="round-controls">
+                                {move || match room_state.get().phase {
+                                    Phase::Idle | Phase::Revealed => view! { cx,
+                                        <button on:click={start_round}>"New Round"</button>
+                                    }.into_view(cx),
+                                    // for when the player never submits
+                                    Phase::Evaluating { .. } => view! { cx,
+                                        <button on:click={skip}>"Skip"</button>
+                                    }.into_view(cx),
+                                    Phase::Bidding { .. } => ().into_view(cx),
+                                }}
                             </div>
+                            <BoardSettings positions={positions} on_board={move |new_board| session.host(|game| game.set_board(new_board))} />
                             <button on:click={end_host}>"End"</button>
                         </div>
                     }.into_any()
                 },
-                NetworkState::Client(conn) => {
+                NetworkState::Client(link) => {
                     log!("rendering client");
                     view! {
                         cx,
                         <div class="network-state-client">
-                            <div class="network-host-id">"Room ID: " {format!("{}", &conn.peer()["ripoff-robots-".len()..])}</div>
-                            <div class="network-players">
-                                <h3>"Players"</h3>
-                                <For each={move || room_state.get().players.iter().map(|(id, name)| (id.to_owned(), name.to_owned())).collect::<Vec<_>>()}
-                                    key=|(id,_name)| id.to_string()
-                                    view=move |cx, (id, name)| {
-                                        view!{
-                                            cx, 
-                                            <div class="network-player">
-                                                <span class="network-player-name">{name}</span>
-                                                <span class="network-player-score">{move || room_state.get().scores.get(&id).map(|x|*x).unwrap_or(0)}</span>
-                                            </div>
-                                        }
-                                    }
-                                    />
-                            </div>
+                            <div class="network-host-id">"Room ID: " {format!("{}", &link.host()["ripoff-robots-".len()..])}</div>
+                            {move || session.metadata.with_value(|metadata| metadata.spectator).then(|| view! { cx,
+                                <div class="network-spectating">"Watching"</div>
+                            })}
+                            {move || room_state.with(|room| room.welcome.is_none()).then(|| view! { cx,
+                                <div class="network-waiting">"Waiting for the host to let you in"</div>
+                            })}
+                            {players}
+                            <Round session={session} />
+                            <Chat session={session} />
                             <button on:click={end_client}>"Leave"</button>
                         </div>
                     }.into_any()
@@ -308,20 +851,150 @@ pub fn Network(cx: Scope, state: RwSignal<NetworkState>, room_state: RwSignal<Ro
     }
 }
 
+/// The profile saved on an earlier visit, or a new one, and
+/// whether it may be saved over. One that can't be loaded is
+/// backed up first, unless a newer build saved it, in which
+/// case it's left for that build and changes aren't saved.
+fn load_profile() -> (Profile, bool) {
+    let storage = window().local_storage().ok().flatten();
+    let saved = storage.as_ref().and_then(|storage| storage.get_item(PROFILE_KEY).ok().flatten());
+    let Some(json) = saved else {
+        return (Profile::new(&mut rand::Rng::from_entropy()), true);
+    };
+    let err = match Profile::load(&json) {
+        Ok(profile) => return (profile, true),
+        Err(err) => err,
+    };
+    let profile = Profile::recover(&json, &mut rand::Rng::from_entropy());
+    if Profile::is_newer(&json) {
+        error!("not saving over a newer profile: {}", err);
+        return (profile, false);
+    }
+    error!("starting a new profile: {}", err);
+    let backed_up = storage.is_some_and(|storage| storage.set_item(PROFILE_BACKUP_KEY, &json).is_ok());
+    if !backed_up {
+        error!("couldn't back up the old profile, so not saving over it");
+    }
+    (profile, backed_up)
+}
+
+fn save_profile(profile: &Profile) {
+    if let Some(storage) = window().local_storage().ok().flatten() {
+        if storage.set_item(PROFILE_KEY, &profile.save()).is_err() {
+            error!("couldn't save the profile");
+        }
+    }
+}
+
+/// The player's preferences, saved for their next visit.
+#[component]
+pub fn ProfileSettings(cx: Scope, profile: RwSignal<Profile>) -> impl IntoView {
+    let set_palette = move |ev| {
+        let palette = if event_target_checked(&ev) { Palette::ColourBlind } else { Palette::Standard };
+        profile.update(|profile| profile.palette = palette);
+    };
+
+    view! { cx,
+        <div class="profile">
+            <label>
+                <input type="checkbox" prop:checked={move || profile.with(|profile| profile.palette == Palette::ColourBlind)}
+                    on:change={set_palette} />
+                "Colour-blind palette"
+            </label>
+            <div class="profile-keys">
+                <For each={move || Direction::ALL.into_iter().enumerate().collect::<Vec<_>>()}
+                    key={|&(i, _)| i}
+                    view=move |cx, (_, direction)| {
+                        view! { cx,
+                            <label class="profile-key">
+                                {direction.id()}
+                                // press a key to bind it
+                                <input type="text" readonly
+                                    prop:value={move || profile.with(|profile| profile.keys.get(direction).to_string())}
+                                    on:keydown={move |ev| {
+                                        ev.prevent_default();
+                                        profile.update(|profile| profile.keys.set(direction, &ev.code()));
+                                    }} />
+                            </label>
+                        }
+                    }
+                    />
+            </div>
+        </div>
+    }
+}
+
 pub fn main() {
     mount_to_body(|cx| {
+        let (profile, writable) = load_profile();
+        let profile = create_rw_signal(cx, profile);
+
+        let board = create_rw_signal(cx, Board::generate(&GenerateOptions::default(), &mut rand::Rng::from_entropy())
+            .expect("default options are valid"));
+        let session = Session {
+            network: create_rw_signal(cx, NetworkState::None),
+            room: create_rw_signal(cx, Default::default()),
+            board,
+            target: create_rw_signal(cx, None),
+            positions: create_rw_signal(cx, board.get_untracked().initial_positions),
+            moves: create_rw_signal(cx, Vec::new()),
+            clock: store_value(cx, Clock::default()),
+            now: create_rw_signal(cx, js_sys::Date::now() as u64),
+            metadata: store_value(cx, net::Metadata {
+                // the same on every visit, to reclaim our score after a reload
+                token: profile.with_untracked(|profile| profile.id.clone()),
+                ..Default::default()
+            }),
+            error: create_rw_signal(cx, String::new()),
+            profile,
+            last_round: create_rw_signal(cx, None),
+        };
+
+        create_effect(cx, move |_| if writable { profile.with(save_profile) });
+        let Session { network: network_state, room: room_state, target, positions, moves, .. } = session;
+
+        // reset robots and moves when the board changes
+        create_effect(cx, move |_| {
+            positions.set(board.get().initial_positions);
+            moves.update(|moves: &mut Vec<(usize, Direction)>| moves.clear());
+        });
+
+        // stream our moves to everyone while demonstrating
+        create_effect(cx, move |played: Option<usize>| {
+            let moves = moves.get();
+            if cx.untrack(|| session.demonstrating()) {
+                match moves.last() {
+                    None => session.send(net::Message::DemoReset(net::DemoResetMessage { player: String::new() })),
+                    Some(&(robot, direction)) if moves.len() > played.unwrap_or(0) => {
+                        session.send(net::Message::DemoMove(net::DemoMoveMessage { player: String::new(), robot, direction }));
+                    },
+                    Some(_) => {},
+                }
+            }
+            moves.len()
+        });
+
+        // keep the clock running and close
+        // bidding once the deadline passes
+        set_interval(move || {
+            let now = session.host_time();
+            session.now.set(now);
+            if let NetworkState::Server(host) = session.network.get_untracked() {
+                host.tick();
+            }
+        }, Duration::from_millis(250));
 
-        let room_state: RwSignal<RoomState> = create_rw_signal(cx, Default::default());
-        let network_state = create_rw_signal(cx, NetworkState::None);
-        let board = create_rw_signal(cx, Board::generate(16, 16));
-        let positions = create_rw_signal(cx, board.get_untracked().initial_positions);
-        let moves = create_rw_signal(cx, Vec::new());
+        // keep measuring the host's clock
+        set_interval(move || session.ping(), PING_INTERVAL);
 
         // clear room state when network state is set to None
         create_effect(cx, move |_| {
             let state = network_state.get();
             match state {
-                NetworkState::None => { room_state.set(Default::default()); }
+                NetworkState::None => {
+                    room_state.set(Default::default());
+                    session.clock.set_value(Clock::default());
+                }
                 _ => {}
             }
         });
@@ -332,156 +1005,44 @@ pub fn main() {
             let state = network_state.get();
             match state {
                 NetworkState::None => {},
-                NetworkState::Server { peer, initialized: false, .. } => {
-                    let _ = Reflect::set(&js_sys::global(), &"peer".into(), peer.as_ref());
-
-                    peer.on("open", &Closure::<dyn Fn()>::new(move || {
-                        log!("connection established to PeerServer")
-                    }).into_js_value());
+                NetworkState::Server(host) => {
+                    if let AnyTransport::PeerJs(peer) = host.transport() {
+                        let _ = Reflect::set(&js_sys::global(), &"peer".into(), peer.as_ref());
 
-                    peer.on("error", &Closure::<dyn Fn(JsValue)>::new(move |err| {
-                        console::error_1(&err);
-                    }).into_js_value());
-
-                    peer.on("close", &Closure::<dyn Fn()>::new(move || {
-                        network_state.set(NetworkState::None);
-                    }).into_js_value());
-
-                    peer.on("connection", &Closure::<dyn Fn(_)>::new(move |conn: peer::DataConnection| {
-                        log!("someone connected :)");
-
-                        let md: JsValue = conn.metadata();
-                        let name = match Reflect::get(&md, &JsValue::from_str("name")).and_then(|v| v.as_string().ok_or(JsValue::NULL)) {
-                            Ok(s) => s,
-                            Err(_) => "Anonymous".to_string()
-                        };
-
-                        network_state.update(|state| {
-                            if let NetworkState::Server { conns, .. } = state {
-                                // Broadcast the PlayerJoin message
-                                peer::broadcast(&conns,
-                                    &net::Message::PlayerJoin(net::PlayerJoinMessage {
-                                        ids: vec![conn.peer()],
-                                        names: vec![name.clone()],
-                                        scores: vec![0],
-                                    })
-                                );
-
-                                // Update network state & room state to include new player
-                                conns.push(conn.clone());
-                                room_state.update(|room| {
-                                    room.players.insert(conn.peer(), name);
-                                });
-                            }
-                        });
-
-                        // Send initiating messages
-                        let conn_clone = conn.clone();
-                        conn.on("open", &Closure::<dyn Fn()>::new(move || {
-                            // Send board state
-                            peer::send(&conn_clone, 
-                                &net::Message::BoardState(net::BoardStateMessage {
-                                    board: board.get_untracked(),
-                                })
-                            );
-
-                            // Send current list of players & scores
-                            let mut ids = Vec::new();
-                            let mut names = Vec::new();
-                            let mut scores = Vec::new();
-                            for (id, name) in room_state.get().players.iter() {
-                                ids.push(id.clone());
-                                names.push(name.clone());
-                                scores.push(room_state.get().scores.get(id).map(|x|*x).unwrap_or(0));
-                            }
-
-                            peer::send(&conn_clone, &net::Message::PlayerJoin(net::PlayerJoinMessage {
-                                ids, names, scores
-                            }))
-                        }).into_js_value());
-
-                        // Broadcast `PlayerLeave` message when
-                        // the player disconnects & update room state.
-                        let id =conn.peer();
-                        conn.on("close", &Closure::<dyn Fn()>::new(move || {
-                            let id = id.clone();
-                            let id_clone = id.clone();
-                            network_state.update(move |state| {
-                                if let NetworkState::Server { conns, .. } = state {
-                                    conns.extract_if(|conn| conn.peer() == id);
-
-                                    peer::broadcast(&conns, 
-                                        &net::Message::PlayerLeave(net::PlayerLeaveMessage {
-                                            id: id,
-                                        })
-                                    );
-                                };
-                            });
-
-                            // Update room state
-                            room_state.update(move |state| {
-                                state.players.remove(&id_clone);
-                                state.scores.remove(&id_clone);
-                            })
+                        peer.on("open", &Closure::<dyn Fn()>::new(move || {
+                            log!("connection established to PeerServer")
                         }).into_js_value());
-                    }).into_js_value());
-
-                    network_state.update(|state| {
-                        if let NetworkState::Server { initialized, .. } = state {
-                            *initialized = true;
-                        }
-                    });
-                },
-
-                NetworkState::Client(ref conn) => {
-                    log!("setting event for client handlers...");
-
-                    conn.on("error", &Closure::<dyn Fn(JsValue)>::new(move |err| {
-                        console::error_1(&err);
-                    }).into_js_value());
-
-                    conn.on("open", &Closure::<dyn Fn()>::new(move || {
-                        log!("connection opened to host!");
-                    }).into_js_value());
+                    }
 
-                    conn.on("close", &Closure::<dyn Fn()>::new(move || {
-                        log!("closing connection...");
+                    host.transport().on_disconnect(move || {
                         network_state.set(NetworkState::None);
-                    }).into_js_value());
-
-                    conn.on("data", &Closure::<dyn Fn(JsValue)>::new(move |data| {
-                        let data: Result<net::Message, _> = serde_wasm_bindgen::from_value(data);
-                        log!("incoming data: {:?}", &data);
-                        match data {
-                            Err(err) => { error!("error parsing incoming message: {:?}", err) },
-                            Ok(net::Message::BoardState(state)) => {
-                                board.set(state.board);
-                            },
-                            Ok(net::Message::PlayerJoin(msg)) => {
-                                for ((id, name), score) in msg.ids.into_iter().zip(msg.names.into_iter()).zip(msg.scores.into_iter()) {
-                                    room_state.update(|state| {
-                                        state.players.insert(id.clone(), name);
-                                        state.scores.insert(id, score);
-                                    });
-                                }
-                            },
-                            Ok(net::Message::PlayerLeave(msg)) => {
-                                room_state.update(|state| { state.players.remove(&msg.id); });
-                            }
-                            _ => unimplemented!(),
-                        }
-                    }).into_js_value());
+                    });
                 },
 
-                _ => {}
+                // the link looks after itself
+                NetworkState::Client(_) => {},
             }
         });
 
 
+        // the solver would give the answer away during a round,
+        // so it only solves the one before, or a board at rest
+        let idle = create_memo(cx, move |_| room_state.with(|room| room.phase == Phase::Idle));
+        let puzzle = Signal::derive(cx, move || match idle.get() {
+            true => Some((board.get(), target.get())),
+            false => session.last_round.get(),
+        });
+
         view! { cx,  
-            <Network board={board.write_only()} state={network_state} room_state={room_state} />
-            <BoardWidget board={board.read_only()} positions={Some(positions)} moves={moves} />
-            <MoveList moves={moves.read_only()} /> }
+            <div class={move || profile.with(|profile| profile.palette.class())}>
+                <Network session={session} positions={positions.read_only()} />
+                <BoardWidget board={board.read_only()} target={target.read_only()} positions={Some(positions)} moves={moves}
+                    locked={Signal::derive(cx, move || session.spectating())}
+                    keys={Signal::derive(cx, move || profile.with(|profile| profile.keys.clone()))} />
+                <MoveList moves={moves.read_only()} />
+                <SolutionWidget puzzle={puzzle} />
+                <ProfileSettings profile={profile} />
+            </div> }
 
     })
-}
\ No newline at end of file
+}
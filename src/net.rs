@@ -2,9 +2,38 @@ use serde::{Deserialize, Serialize};
 
 use crate::board;
 
+/// The version of these messages. It goes up whenever
+/// a change would confuse peers on an older build.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The oldest protocol version a host still accepts.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional parts of the protocol this build supports.
+/// A host only sends a client the messages of features
+/// they both support.
+pub const FEATURES: &[&str] = &[CLOCK, DEMO, CHAT];
+
+/// `Ping` and `Pong`, for measuring the host's clock.
+pub const CLOCK: &str = "clock";
+
+/// `DemoMove` and `DemoReset`, for watching demonstrations.
+pub const DEMO: &str = "demo";
+
+/// `Chat`, for talking during the game.
+pub const CHAT: &str = "chat";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]
 pub enum Message {
+    Hello(HelloMessage),
+    Welcome(WelcomeMessage),
+    Pending(PendingMessage),
+
+    Ping(PingMessage),
+    Pong(PongMessage),
+
     PlayerJoin(PlayerJoinMessage),
     PlayerLeave(PlayerLeaveMessage),
 
@@ -12,7 +41,138 @@ pub enum Message {
     StartBid(StartBidMessage),
     UpdateBid(UpdateBidMessage),
     MakeBid(MakeBidMessage),
-    StartEval(StartEvalMessage)
+    StartEval(StartEvalMessage),
+    DemoMove(DemoMoveMessage),
+    DemoReset(DemoResetMessage),
+    SubmitMoves(SubmitMovesMessage),
+    Verdict(VerdictMessage),
+    EndRound(EndRoundMessage),
+
+    Chat(ChatMessage),
+
+    Error(ErrorMessage)
+}
+
+impl Message {
+    /// The feature a peer needs to understand this message, if any.
+    pub fn feature(&self) -> Option<&'static str> {
+        match self {
+            Message::Ping(_) | Message::Pong(_) => Some(CLOCK),
+            Message::DemoMove(_) | Message::DemoReset(_) => Some(DEMO),
+            Message::Chat(_) => Some(CHAT),
+            _ => None,
+        }
+    }
+}
+
+/// Sent by a client when it opens its connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    #[serde(default)]
+    pub name: String,
+
+    /// Identifies the player across reconnections, so the host
+    /// can give back their score. Whoever presents it gets
+    /// that score, so clients shouldn't share it.
+    #[serde(default)]
+    pub token: String,
+
+    /// Joins to watch, without playing.
+    #[serde(default)]
+    pub spectator: bool,
+
+    /// The room's password, if the host set one.
+    #[serde(default)]
+    pub password: String,
+}
+
+/// Sent first by a client, before the host
+/// will let it join the game.
+///
+/// Client -> Host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HelloMessage {
+    pub version: u32,
+    pub features: Vec<String>,
+}
+
+impl HelloMessage {
+    /// What this build says.
+    pub fn current() -> Self {
+        HelloMessage {
+            version: PROTOCOL_VERSION,
+            features: FEATURES.iter().map(|feature| feature.to_string()).collect(),
+        }
+    }
+
+    /// Works out what the host and this client can speak,
+    /// or why they can't play together.
+    pub fn negotiate(&self) -> Result<WelcomeMessage, String> {
+        if self.version < MIN_PROTOCOL_VERSION {
+            return Err(format!(
+                "Your game is out of date (protocol version {}, the host needs {} or newer). Reload the page to update it.",
+                self.version, MIN_PROTOCOL_VERSION,
+            ));
+        }
+        if self.version > PROTOCOL_VERSION {
+            return Err(format!(
+                "The host's game is out of date (protocol version {}, yours is {}). Ask them to reload the page.",
+                PROTOCOL_VERSION, self.version,
+            ));
+        }
+        Ok(WelcomeMessage {
+            version: self.version,
+            features: FEATURES.iter()
+                .filter(|&&feature| self.features.iter().any(|theirs| theirs == feature))
+                .map(|feature| feature.to_string())
+                .collect(),
+            approve: false,
+        })
+    }
+}
+
+/// Sent in reply to a compatible `Hello`, with the
+/// features the host will use with this client. Sent
+/// again whenever the host changes `approve`.
+///
+/// Host -> Client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WelcomeMessage {
+    pub version: u32,
+    pub features: Vec<String>,
+
+    /// Whether the host approves who joins, so
+    /// whoever takes over as host keeps doing so.
+    #[serde(default)]
+    pub approve: bool,
+}
+
+/// Sent in reply to a `Hello` when the host must approve
+/// the client first, so it knows the host is there.
+///
+/// Host -> Client
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingMessage {}
+
+/// Sent to measure the round trip to the host
+/// and how far apart the two clocks are.
+///
+/// Client -> Host
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PingMessage {
+    /// The client's time when sending.
+    pub sent: u64,
+}
+
+/// Sent in reply to a `Ping`.
+///
+/// Host -> Client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PongMessage {
+    /// Copied from the `Ping`.
+    pub sent: u64,
+    /// The host's time when replying.
+    pub host_time: u64,
 }
 
 /// Sent when a player joins.
@@ -33,6 +193,10 @@ pub struct PlayerJoinMessage {
 #[serde(rename_all = "snake_case")]
 pub struct PlayerLeaveMessage {
     pub id: String,
+
+    /// Why the host removed them, if it did.
+    #[serde(default)]
+    pub reason: Option<String>,
 }
 
 /// Sent when the board state changes.
@@ -42,6 +206,10 @@ pub struct PlayerLeaveMessage {
 #[serde(rename_all = "snake_case")]
 pub struct BoardStateMessage {
     pub board: board::Board,
+
+    /// Index into `board.targets` of the
+    /// active target for this round.
+    pub target: Option<usize>,
 }
 
 /// Sent when the bidding
@@ -50,6 +218,7 @@ pub struct BoardStateMessage {
 /// Host -> All Clients
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StartBidMessage {
+    /// When bidding closes, in host time.
     pub end_time: u64,
 }
 
@@ -59,6 +228,9 @@ pub struct StartBidMessage {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MakeBidMessage {
     pub bid: u8,
+    /// When the bid was made, in host time.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
 }
 
 /// Sent when a new bid is made.
@@ -69,6 +241,11 @@ pub struct UpdateBidMessage {
     /// The ID of the player
     pub player: String,
     pub bid: u8,
+
+    /// When the host took the bid, in host time, so whoever
+    /// takes over as host breaks ties the same way.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
 }
 
 /// Sent when a player's bid is up for evaluation.
@@ -77,6 +254,93 @@ pub struct UpdateBidMessage {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StartEvalMessage {
     pub player: String,
+
+    /// The bidders still to demonstrate after this one, in order,
+    /// so whoever takes over as host carries on with the same ones.
+    #[serde(default)]
+    pub queue: Vec<String>,
+}
+
+/// Sent for each move the evaluated player makes
+/// while demonstrating, so others can watch.
+///
+/// Client -> Host -> All Clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DemoMoveMessage {
+    /// The demonstrating player, filled in by the host.
+    #[serde(default)]
+    pub player: String,
+    pub robot: usize,
+    pub direction: board::Direction,
 }
 
+/// Sent when the evaluated player puts the
+/// robots back to start over their demonstration.
+///
+/// Client -> Host -> All Clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DemoResetMessage {
+    /// The demonstrating player, filled in by the host.
+    #[serde(default)]
+    pub player: String,
+}
 
+/// Sent by the player whose bid is being
+/// evaluated to demonstrate their solution.
+///
+/// Client -> Host
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmitMovesMessage {
+    /// Robot and direction of each move, in order.
+    pub moves: Vec<(usize, board::Direction)>,
+}
+
+/// Sent once the host has replayed a player's moves.
+///
+/// Host -> All Clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerdictMessage {
+    pub player: String,
+    pub moves: Vec<(usize, board::Direction)>,
+    /// Whether the moves fit the bid and
+    /// bring the right robot to the target.
+    pub success: bool,
+}
+
+/// Sent when a round is over, either because a
+/// player solved it or because every bidder failed.
+/// A new round starts with the next `BoardState`.
+///
+/// Host -> All Clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndRoundMessage {
+    /// The ID of the player who solved the round.
+    pub winner: Option<String>,
+    /// The winner's new score.
+    pub score: u32,
+}
+
+/// A line of chat. Clients send only the text; the host
+/// fills in who said it and when before passing it on.
+///
+/// Client -> Host -> All Clients
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    #[serde(default)]
+    pub player: String,
+    #[serde(default)]
+    pub name: String,
+    /// When the host received it, in host time.
+    #[serde(default)]
+    pub time: u64,
+    pub text: String,
+}
+
+/// Sent when the host rejects a client's message.
+///
+/// Host -> Client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorMessage {
+    /// Why the message was rejected, for display.
+    pub reason: String,
+}
@@ -1,8 +1,13 @@
 use std::borrow::Borrow;
 
 use js_sys::{Object, Function, Reflect, Array};
+use robots::net::{Message, Metadata};
+use robots::transport::{Connection, Transport};
 use serde::Serialize;
-use wasm_bindgen::{JsValue, prelude::wasm_bindgen};
+use wasm_bindgen::{JsValue, prelude::{wasm_bindgen, Closure}};
+use web_sys::console;
+
+use crate::object;
 
 #[wasm_bindgen]
 extern "C" {
@@ -55,9 +60,58 @@ pub fn send(conn: &DataConnection, data: &impl Serialize) {
     conn.send(&serde_wasm_bindgen::to_value(data).expect("can't serialize data"));
 }
 
-pub fn broadcast(conns: &[DataConnection], data: &impl Serialize) {
-    let value = serde_wasm_bindgen::to_value(data).expect("can't serialize data");
-    for conn in conns {
-        conn.send(&value);
+impl Connection for DataConnection {
+    fn peer(&self) -> String {
+        DataConnection::peer(self)
+    }
+
+    fn metadata(&self) -> Metadata {
+        serde_wasm_bindgen::from_value(DataConnection::metadata(self)).unwrap_or_default()
+    }
+
+    fn send(&self, message: &Message) {
+        send(self, message);
+    }
+
+    fn close(&self) {
+        DataConnection::close(self);
+    }
+
+    fn on_open(&self, f: impl Fn() + 'static) {
+        self.on("open", &Closure::<dyn Fn()>::new(f).into_js_value());
+    }
+
+    fn on_data(&self, f: impl Fn(Message) + 'static) {
+        self.on("data", &Closure::<dyn Fn(JsValue)>::new(move |data| {
+            match serde_wasm_bindgen::from_value(data) {
+                Ok(message) => f(message),
+                Err(err) => console::error_1(&format!("error parsing incoming message: {:?}", err).into()),
+            }
+        }).into_js_value());
+    }
+
+    fn on_close(&self, f: impl Fn() + 'static) {
+        self.on("close", &Closure::<dyn Fn()>::new(f).into_js_value());
+    }
+}
+
+impl Transport for Peer {
+    type Connection = DataConnection;
+
+    fn id(&self) -> String {
+        Peer::id(self)
+    }
+
+    fn connect(&self, id: &str, metadata: &Metadata) -> DataConnection {
+        let metadata = serde_wasm_bindgen::to_value(metadata).expect("can't serialize metadata");
+        Peer::connect(self, id, &object!{ "metadata" => metadata }.into())
+    }
+
+    fn on_connection(&self, f: impl Fn(DataConnection) + 'static) {
+        self.on("connection", &Closure::<dyn Fn(DataConnection)>::new(f).into_js_value());
     }
-}
\ No newline at end of file
+
+    fn destroy(&self) {
+        Peer::destroy(self);
+    }
+}
@@ -0,0 +1,211 @@
+//! The player's profile and preferences, which the browser
+//! keeps between visits.
+//!
+//! A profile is saved as JSON along with the version of its
+//! layout. To change the layout, bump `VERSION` and add a step
+//! to `MIGRATIONS` that rewrites a profile saved by the previous
+//! version, so profiles saved by older builds still load.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::board::Direction;
+use crate::rand::Rng;
+
+/// The version of the profile layout.
+pub const VERSION: u32 = 1;
+
+/// Rewrites a saved profile from one version to the next.
+type Migration = fn(&mut Value);
+
+/// `MIGRATIONS[i]` upgrades a profile from version `i + 1`.
+const MIGRATIONS: &[Migration] = &[];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// The name shown to other players.
+    pub name: String,
+
+    /// Stays the same across visits, so a host can give back a
+    /// player's score after they reload the page. It's sent as
+    /// the session token, so it shouldn't be shown to anyone.
+    pub id: String,
+
+    pub palette: Palette,
+    pub keys: KeyBindings,
+}
+
+/// Colours for the robots and their targets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Palette {
+    #[default]
+    Standard,
+    /// Colours told apart with any kind of colour blindness.
+    ColourBlind,
+}
+
+impl Palette {
+    /// The CSS class that applies this palette.
+    pub fn class(self) -> &'static str {
+        match self {
+            Palette::Standard => "palette-standard",
+            Palette::ColourBlind => "palette-colour-blind",
+        }
+    }
+}
+
+/// Which keys move the selected robot, as `KeyboardEvent.code`s.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub up: String,
+    pub down: String,
+    pub left: String,
+    pub right: String,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            up: "ArrowUp".to_string(),
+            down: "ArrowDown".to_string(),
+            left: "ArrowLeft".to_string(),
+            right: "ArrowRight".to_string(),
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn get(&self, direction: Direction) -> &str {
+        match direction {
+            Direction::Up => &self.up,
+            Direction::Down => &self.down,
+            Direction::Left => &self.left,
+            Direction::Right => &self.right,
+        }
+    }
+
+    pub fn set(&mut self, direction: Direction, code: &str) {
+        let key = match direction {
+            Direction::Up => &mut self.up,
+            Direction::Down => &mut self.down,
+            Direction::Left => &mut self.left,
+            Direction::Right => &mut self.right,
+        };
+        *key = code.to_string();
+    }
+
+    /// The direction the key `code` moves in, if any.
+    pub fn direction(&self, code: &str) -> Option<Direction> {
+        Direction::ALL.into_iter().find(|&direction| self.get(direction) == code)
+    }
+}
+
+/// A profile as saved, with the version of its layout.
+#[derive(Serialize)]
+struct Saved<'a> {
+    version: u32,
+    #[serde(flatten)]
+    profile: &'a Profile,
+}
+
+impl Profile {
+    /// A profile with default preferences and a new ID.
+    pub fn new(rng: &mut Rng) -> Self {
+        Profile { id: format!("{:x}", rng.next_u64()), ..Default::default() }
+    }
+
+    /// Reads a profile saved by this or an older version,
+    /// or says why it can't.
+    pub fn load(json: &str) -> Result<Profile, String> {
+        let mut value: Value = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        let version = value.get("version").and_then(Value::as_u64).ok_or("the profile has no version")?;
+        if version == 0 || version > VERSION as u64 {
+            return Err(format!("can't read a profile saved as version {}", version));
+        }
+        for migrate in &MIGRATIONS[version as usize - 1..] {
+            migrate(&mut value);
+        }
+        let profile: Profile = serde_json::from_value(value).map_err(|err| err.to_string())?;
+        if profile.id.is_empty() {
+            return Err("the profile has no ID".to_string());
+        }
+        Ok(profile)
+    }
+
+    /// A new profile to use in place of `json`, which couldn't be
+    /// loaded. It keeps the old ID and name where it can find them,
+    /// so a host still recognizes the player.
+    pub fn recover(json: &str, rng: &mut Rng) -> Self {
+        let value = serde_json::from_str::<Value>(json).unwrap_or_default();
+        let field = |key: &str| value.get(key).and_then(Value::as_str).filter(|field| !field.is_empty()).map(str::to_string);
+        let mut profile = match field("id") {
+            Some(id) => Profile { id, ..Default::default() },
+            None => Profile::new(rng),
+        };
+        profile.name = field("name").unwrap_or_default();
+        profile
+    }
+
+    /// Whether `json` was saved by a newer build. Saving over
+    /// it would lose whatever that build added, so it's left be.
+    pub fn is_newer(json: &str) -> bool {
+        serde_json::from_str::<Value>(json).ok()
+            .and_then(|value| value.get("version").and_then(Value::as_u64))
+            .is_some_and(|version| version > VERSION as u64)
+    }
+
+    /// The profile as JSON, for `load`.
+    pub fn save(&self) -> String {
+        serde_json::to_string(&Saved { version: VERSION, profile: self }).expect("profiles serialize")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_version_has_a_migration() {
+        assert_eq!(MIGRATIONS.len(), VERSION as usize - 1);
+    }
+
+    #[test]
+    fn saved_profiles_load() {
+        let mut profile = Profile::new(&mut Rng::new(1));
+        profile.name = "Alice".into();
+        profile.palette = Palette::ColourBlind;
+        profile.keys.set(Direction::Up, "KeyW");
+        assert_eq!(Profile::load(&profile.save()), Ok(profile.clone()));
+        assert!(matches!(profile.keys.direction("KeyW"), Some(Direction::Up)));
+        assert!(profile.keys.direction("ArrowUp").is_none());
+
+        // anything missing takes its default
+        let sparse = Profile::load(r#"{"version": 1, "id": "abc"}"#).unwrap();
+        assert_eq!(sparse, Profile { id: "abc".into(), ..Default::default() });
+    }
+
+    #[test]
+    fn unreadable_profiles_are_refused() {
+        for json in ["", "[]", r#"{"id": "abc"}"#, r#"{"version": 99, "id": "abc"}"#, r#"{"version": 1}"#] {
+            assert!(Profile::load(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn unreadable_profiles_keep_the_player() {
+        let newer = r#"{"version": 99, "id": "abc", "name": "Alice", "keys": "wasd"}"#;
+        assert!(Profile::is_newer(newer));
+        let recovered = Profile::recover(newer, &mut Rng::new(1));
+        assert_eq!(recovered, Profile { id: "abc".into(), name: "Alice".into(), ..Default::default() });
+
+        let current = Profile::new(&mut Rng::new(1)).save();
+        assert!(!Profile::is_newer(&current));
+        for json in ["", "[]", r#"{"version": 1, "id": ""}"#] {
+            assert!(!Profile::is_newer(json));
+            assert!(!Profile::recover(json, &mut Rng::new(2)).id.is_empty(), "{}", json);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! The four double-sided quarter boards of the physical game.
+//!
+//! Each side is described as the top-left quarter of a 16x16
+//! board, with the center block in its bottom-right corner.
+//! The other quarters are rotated into place clockwise.
+
+use crate::board::{self, Board, Direction, Symbol, Target, RED, YELLOW, GREEN, BLUE};
+use crate::rand::Rng;
+use Direction::{Up as N, Down as S, Left as W, Right as E};
+
+/// Width and height of a quarter board.
+pub const SIZE: usize = 8;
+
+/// One side of a quarter board.
+#[derive(Debug, Clone, Copy)]
+pub struct Quadrant {
+    /// Column of the wall to the right of tile (x, 0).
+    pub top_edge: usize,
+
+    /// Row of the wall below tile (0, y).
+    pub left_edge: usize,
+
+    /// Target tiles and the two walls forming their L,
+    /// one per color in the order red, yellow, green, blue.
+    pub targets: [(usize, usize, [Direction; 2]); 4],
+
+    /// The vortex and its walls, if this piece has it.
+    pub vortex: Option<(usize, usize, [Direction; 2])>,
+}
+
+/// The four pieces, each with two sides.
+///
+/// Piece `p` gives color `c` the symbol `Symbol::COLORED[(p + c) % 4]`,
+/// so any four distinct pieces carry every color and symbol once.
+pub const PIECES: [[Quadrant; 2]; 4] = [
+    [
+        Quadrant {
+            top_edge: 4, left_edge: 3,
+            targets: [(1, 2, [S, E]), (5, 1, [S, W]), (2, 5, [N, E]), (6, 4, [N, W])],
+            vortex: Some((4, 6, [N, E])),
+        },
+        Quadrant {
+            top_edge: 2, left_edge: 5,
+            targets: [(2, 1, [S, W]), (6, 2, [S, E]), (1, 4, [N, W]), (4, 5, [N, E])],
+            vortex: Some((3, 3, [S, E])),
+        },
+    ],
+    [
+        Quadrant {
+            top_edge: 5, left_edge: 2,
+            targets: [(3, 1, [S, E]), (6, 3, [S, W]), (1, 4, [N, E]), (4, 5, [N, W])],
+            vortex: None,
+        },
+        Quadrant {
+            top_edge: 3, left_edge: 4,
+            targets: [(5, 1, [S, W]), (1, 2, [N, W]), (3, 4, [S, E]), (6, 5, [N, E])],
+            vortex: None,
+        },
+    ],
+    [
+        Quadrant {
+            top_edge: 3, left_edge: 4,
+            targets: [(2, 2, [N, W]), (5, 2, [N, E]), (1, 6, [S, E]), (5, 5, [S, W])],
+            vortex: None,
+        },
+        Quadrant {
+            top_edge: 5, left_edge: 2,
+            targets: [(4, 1, [N, E]), (1, 3, [S, W]), (6, 4, [S, E]), (3, 6, [N, W])],
+            vortex: None,
+        },
+    ],
+    [
+        Quadrant {
+            top_edge: 2, left_edge: 5,
+            targets: [(1, 1, [S, E]), (4, 2, [N, W]), (6, 5, [S, W]), (2, 5, [N, E])],
+            vortex: None,
+        },
+        Quadrant {
+            top_edge: 4, left_edge: 3,
+            targets: [(6, 1, [N, W]), (3, 3, [N, E]), (1, 5, [S, E]), (5, 6, [S, W])],
+            vortex: None,
+        },
+    ],
+];
+
+/// Rotates a tile and wall side of the top-left
+/// quarter clockwise by `turns` quarter turns.
+fn rotate((mut x, mut y, mut side): (usize, usize, Direction), turns: usize) -> (usize, usize, Direction) {
+    for _ in 0..turns {
+        (x, y) = (SIZE - 1 - y, x);
+        side = match side {
+            N => E,
+            E => S,
+            S => W,
+            W => N,
+        };
+    }
+    (x, y, side)
+}
+
+impl Quadrant {
+    /// Places this side's walls and targets on a 16x16 board,
+    /// rotated clockwise by `turns` quarter turns.
+    pub fn place(&self, board: &mut Board, piece: usize, turns: usize) {
+        let (dx, dy) = [(0, 0), (SIZE, 0), (SIZE, SIZE), (0, SIZE)][turns];
+        let wall = |board: &mut Board, x, y, side| {
+            let (x, y, side) = rotate((x, y, side), turns);
+            board.set_wall((y + dy) * board.width + x + dx, side);
+        };
+
+        wall(board, self.top_edge, 0, E);
+        wall(board, 0, self.left_edge, S);
+
+        let colored = [RED, YELLOW, GREEN, BLUE].into_iter()
+            .map(|robot| (Some(robot), Symbol::COLORED[(piece + robot) % 4]));
+        let vortex = self.vortex.map(|vortex| (vortex, (None, Symbol::Vortex)));
+        for ((x, y, sides), (robot, symbol)) in self.targets.into_iter().zip(colored).chain(vortex) {
+            for side in sides {
+                wall(board, x, y, side);
+            }
+            let (x, y, _) = rotate((x, y, N), turns);
+            board.targets.push(Target { tile: (y + dy) * board.width + x + dx, robot, symbol });
+        }
+    }
+}
+
+/// Places four random quarter boards, each
+/// on a random side, onto a 16x16 board.
+pub fn assemble(board: &mut Board, rng: &mut Rng) {
+    let mut pieces = [0, 1, 2, 3];
+    board::shuffle(&mut pieces, rng);
+
+    for (turns, piece) in pieces.into_iter().enumerate() {
+        let side = rng.uniform(0, 2);
+        PIECES[piece][side].place(board, piece, turns);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::GenerateOptions;
+
+    #[test]
+    fn every_token_once() {
+        for seed in 0..50 {
+            let board = Board::generate(&GenerateOptions::default(), &mut Rng::new(seed)).unwrap();
+            assert_eq!(board.targets.len(), 17);
+            for (i, a) in board.targets.iter().enumerate() {
+                for b in &board.targets[i+1..] {
+                    assert_ne!(a.tile, b.tile);
+                    assert!(a.robot != b.robot || a.symbol != b.symbol);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn targets_sit_in_wall_corners() {
+        let board = Board::generate(&GenerateOptions::default(), &mut Rng::new(3)).unwrap();
+        let table = board.move_table();
+        for target in &board.targets {
+            let stopped = Direction::ALL.into_iter()
+                .filter(|&direction| table.stop(target.tile, direction) == target.tile)
+                .count();
+            assert!(stopped >= 2, "target {:?} is not in a corner", target);
+        }
+    }
+}
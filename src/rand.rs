@@ -1,8 +1,52 @@
-
-pub fn uniform(a: usize, b: usize) -> usize {
-    (unsafe {js_sys::Math::random()} * (b as f64 - a as f64) + a as f64) as usize
+/// A seedable pseudo-random number generator (SplitMix64).
+///
+/// The same seed produces the same sequence everywhere,
+/// so a board can be reproduced from its seed alone.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
 }
 
-pub fn bool() -> bool {
-    (unsafe {js_sys::Math::random()} * 2.0) as usize == 0
-}
\ No newline at end of file
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    /// Creates a generator seeded from `Math.random`.
+    #[cfg(feature = "web")]
+    pub fn from_entropy() -> Self {
+        let hi = (js_sys::Math::random() * u32::MAX as f64) as u64;
+        let lo = (js_sys::Math::random() * u32::MAX as f64) as u64;
+        Rng::new(hi << 32 | lo)
+    }
+
+    /// Creates a generator seeded from the
+    /// standard library's random hasher keys.
+    #[cfg(not(feature = "web"))]
+    pub fn from_entropy() -> Self {
+        use std::hash::{BuildHasher, Hasher};
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u64(std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64));
+        Rng::new(hasher.finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `a..b`.
+    pub fn uniform(&mut self, a: usize, b: usize) -> usize {
+        debug_assert!(a < b, "empty range {}..{}", a, b);
+        a + ((self.next_u64() as u128 * (b - a) as u128) >> 64) as usize
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.next_u64() >> 63 == 0
+    }
+}
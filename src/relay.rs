@@ -0,0 +1,238 @@
+//! Relaying game messages between peers over WebSockets,
+//! for networks where the PeerJS servers can't be reached.
+//!
+//! Every socket starts by sending `Hello` to claim a peer ID
+//! in a room. After that it works like PeerJS: a peer opens
+//! connections to other peers in the same room by ID, and
+//! `Data` frames carry `net::Message`s along them. The relay
+//! rewrites the `peer` of each frame it forwards to name the
+//! sender, so both sides always see the other end's ID.
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::net::Metadata;
+
+mod peer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod client;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod server;
+
+pub use peer::{RelayConnection, RelayPeer, Socket, WeakRelayPeer};
+
+/// One WebSocket text message, as JSON.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "frame", rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]
+pub enum Frame {
+    /// Claims `peer` as this socket's ID in `room`.
+    ///
+    /// Peer -> Relay
+    Hello { room: String, peer: String },
+
+    /// Sent by the relay once `Hello` is accepted.
+    ///
+    /// Relay -> Peer
+    Welcome { peer: String },
+
+    /// Asks to open a connection to `peer`.
+    ///
+    /// Peer -> Relay
+    Connect { peer: String, metadata: Metadata },
+
+    /// A connection with `peer` is open. The peer that asked
+    /// gets this too, once the other end has been told.
+    ///
+    /// Relay -> Peer
+    Open { peer: String, metadata: Metadata },
+
+    /// A `net::Message` for, or from, `peer`. The
+    /// relay passes it on without looking inside.
+    ///
+    /// Peer <-> Relay
+    Data { peer: String, message: serde_json::Value },
+
+    /// The connection with `peer` is closed.
+    ///
+    /// Peer <-> Relay
+    Close { peer: String },
+
+    /// Why the relay refused a frame.
+    ///
+    /// Relay -> Peer
+    Error { reason: String },
+}
+
+/// Identifies a socket connected to the relay.
+pub type SocketId = usize;
+
+/// Frames for the relay to send, in order.
+pub type Outbox = Vec<(SocketId, Frame)>;
+
+#[derive(Debug, Clone)]
+struct Member {
+    room: String,
+    peer: String,
+    /// Peers this socket has open connections with.
+    links: BTreeSet<String>,
+}
+
+/// Who is where, and who is connected to whom.
+/// Like `game::Game`, it never touches the network.
+#[derive(Debug, Clone, Default)]
+pub struct Relay {
+    sockets: HashMap<SocketId, Member>,
+    /// Sockets by room and peer ID.
+    peers: HashMap<(String, String), SocketId>,
+}
+
+impl Relay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The socket of `peer` in the same room as `socket`.
+    fn find(&self, socket: SocketId, peer: &str) -> Option<SocketId> {
+        let room = &self.sockets.get(&socket)?.room;
+        self.peers.get(&(room.clone(), peer.to_string())).copied()
+    }
+
+    fn error(socket: SocketId, reason: &str) -> Outbox {
+        vec![(socket, Frame::Error { reason: reason.to_string() })]
+    }
+
+    /// Handles a frame from `socket`.
+    pub fn handle(&mut self, socket: SocketId, frame: Frame) -> Outbox {
+        let Some(me) = self.sockets.get(&socket).map(|socket| socket.peer.clone()) else {
+            let Frame::Hello { room, peer } = frame else {
+                return Self::error(socket, "say hello first");
+            };
+            let key = (room.clone(), peer.clone());
+            if peer.is_empty() || self.peers.contains_key(&key) {
+                return Self::error(socket, "that peer ID is taken");
+            }
+            self.peers.insert(key, socket);
+            self.sockets.insert(socket, Member { room, peer: peer.clone(), links: BTreeSet::new() });
+            return vec![(socket, Frame::Welcome { peer })];
+        };
+
+        match frame {
+            Frame::Connect { peer, metadata } => {
+                let Some(other) = self.find(socket, &peer).filter(|&other| other != socket) else {
+                    return vec![(socket, Frame::Close { peer })];
+                };
+                self.link(socket, &peer);
+                self.link(other, &me);
+                vec![
+                    (other, Frame::Open { peer: me, metadata: metadata.clone() }),
+                    (socket, Frame::Open { peer, metadata }),
+                ]
+            },
+            Frame::Data { peer, message } => {
+                match self.find(socket, &peer) {
+                    Some(other) if self.sockets[&socket].links.contains(&peer) =>
+                        vec![(other, Frame::Data { peer: me, message })],
+                    _ => Self::error(socket, "not connected to that peer"),
+                }
+            },
+            Frame::Close { peer } => self.unlink(socket, &peer),
+            Frame::Hello { .. } => Self::error(socket, "already said hello"),
+            Frame::Welcome { .. } | Frame::Open { .. } | Frame::Error { .. } =>
+                Self::error(socket, "only the relay sends that"),
+        }
+    }
+
+    fn link(&mut self, socket: SocketId, peer: &str) {
+        if let Some(socket) = self.sockets.get_mut(&socket) {
+            socket.links.insert(peer.to_string());
+        }
+    }
+
+    /// Closes the connection between `socket` and `peer`, telling the other end.
+    fn unlink(&mut self, socket: SocketId, peer: &str) -> Outbox {
+        let Some(me) = self.sockets.get_mut(&socket) else {
+            return Vec::new();
+        };
+        if !me.links.remove(peer) {
+            return Vec::new();
+        }
+        let me = me.peer.clone();
+        match self.find(socket, peer) {
+            Some(other) => {
+                if let Some(other_socket) = self.sockets.get_mut(&other) {
+                    other_socket.links.remove(&me);
+                }
+                vec![(other, Frame::Close { peer: me })]
+            },
+            None => Vec::new(),
+        }
+    }
+
+    /// Forgets a socket that went away, closing its connections.
+    pub fn disconnect(&mut self, socket: SocketId) -> Outbox {
+        let Some(links) = self.sockets.get(&socket).map(|socket| socket.links.clone()) else {
+            return Vec::new();
+        };
+        let outbox = links.iter().flat_map(|peer| self.unlink(socket, peer)).collect();
+        if let Some(gone) = self.sockets.remove(&socket) {
+            self.peers.remove(&(gone.room, gone.peer));
+        }
+        outbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{Message, PingMessage};
+
+    fn hello(relay: &mut Relay, socket: SocketId, peer: &str) {
+        let outbox = relay.handle(socket, Frame::Hello { room: "room".into(), peer: peer.into() });
+        assert_eq!(outbox, vec![(socket, Frame::Welcome { peer: peer.into() })]);
+    }
+
+    fn ping(sent: u64) -> serde_json::Value {
+        serde_json::to_value(Message::Ping(PingMessage { sent })).unwrap()
+    }
+
+    #[test]
+    fn routes_between_connected_peers() {
+        let mut relay = Relay::new();
+        hello(&mut relay, 0, "host");
+        hello(&mut relay, 1, "alice");
+
+        let metadata = Metadata { name: "Alice".into(), ..Default::default() };
+        let outbox = relay.handle(1, Frame::Connect { peer: "host".into(), metadata: metadata.clone() });
+        assert_eq!(outbox, vec![
+            (0, Frame::Open { peer: "alice".into(), metadata: metadata.clone() }),
+            (1, Frame::Open { peer: "host".into(), metadata }),
+        ]);
+
+        // the receiver sees who sent it
+        let outbox = relay.handle(1, Frame::Data { peer: "host".into(), message: ping(1) });
+        assert_eq!(outbox, vec![(0, Frame::Data { peer: "alice".into(), message: ping(1) })]);
+        let outbox = relay.handle(0, Frame::Data { peer: "alice".into(), message: ping(2) });
+        assert_eq!(outbox, vec![(1, Frame::Data { peer: "host".into(), message: ping(2) })]);
+
+        // leaving closes the other end
+        assert_eq!(relay.disconnect(1), vec![(0, Frame::Close { peer: "alice".into() })]);
+        assert!(matches!(relay.handle(0, Frame::Data { peer: "alice".into(), message: ping(3) })[..], [(0, Frame::Error { .. })]));
+    }
+
+    #[test]
+    fn rooms_are_separate() {
+        let mut relay = Relay::new();
+        hello(&mut relay, 0, "host");
+        relay.handle(1, Frame::Hello { room: "other".into(), peer: "host".into() });
+        let outbox = relay.handle(1, Frame::Connect { peer: "host".into(), metadata: Metadata::default() });
+        // "host" in the other room is itself
+        assert_eq!(outbox, vec![(1, Frame::Close { peer: "host".into() })]);
+
+        // taken IDs are refused
+        assert!(matches!(relay.handle(2, Frame::Hello { room: "room".into(), peer: "host".into() })[..], [(2, Frame::Error { .. })]));
+        // nothing but hello before hello
+        assert!(matches!(relay.handle(3, Frame::Connect { peer: "host".into(), metadata: Metadata::default() })[..], [(3, Frame::Error { .. })]));
+    }
+}
@@ -0,0 +1,105 @@
+//! A native socket to a relay server, so a `RelayPeer`
+//! can run outside the browser, e.g. in a headless host.
+
+use std::cell::RefCell;
+use std::io;
+use std::net::TcpStream;
+use std::rc::Rc;
+use std::time::Duration;
+
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message as WsMessage, WebSocket};
+
+use super::{Frame, RelayPeer, Socket};
+
+type Stream = WebSocket<MaybeTlsStream<TcpStream>>;
+
+#[derive(Clone)]
+struct NativeSocket {
+    ws: Rc<RefCell<Stream>>,
+}
+
+impl NativeSocket {
+    fn write(&self, frame: &Frame) -> io::Result<()> {
+        let text = serde_json::to_string(frame).expect("frames serialize");
+        self.ws.borrow_mut().send(WsMessage::Text(text)).map_err(io::Error::other)
+    }
+
+    /// Waits up to `timeout` for the next frame.
+    /// `Ok(None)` means nothing arrived in time.
+    fn read(&self, timeout: Duration) -> io::Result<Option<Frame>> {
+        let mut ws = self.ws.borrow_mut();
+        if let MaybeTlsStream::Plain(stream) = ws.get_ref() {
+            stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        }
+        match ws.read() {
+            Ok(WsMessage::Text(text)) => serde_json::from_str(&text)
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Ok(WsMessage::Close(_)) => Err(io::ErrorKind::ConnectionAborted.into()),
+            Ok(_) => Ok(None),
+            Err(tungstenite::Error::Io(err)) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Ok(None),
+            Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) =>
+                Err(io::ErrorKind::ConnectionAborted.into()),
+            Err(err) => Err(io::Error::other(err)),
+        }
+    }
+}
+
+impl Socket for NativeSocket {
+    fn send(&self, frame: &Frame) {
+        // a failed write shows up as a failed read in `Client::poll`
+        let _ = self.write(frame);
+    }
+
+    fn close(&self) {
+        let _ = self.ws.borrow_mut().close(None);
+    }
+}
+
+/// A relay peer and the socket it runs on. Nothing
+/// arrives unless `poll` is called.
+pub struct Client {
+    socket: NativeSocket,
+    peer: RelayPeer,
+}
+
+impl Client {
+    /// Connects to the relay at `url` as peer `id` in `room`,
+    /// waiting until the relay accepts the ID.
+    pub fn connect(url: &str, room: &str, id: &str) -> io::Result<Client> {
+        let (ws, _) = tungstenite::connect(url).map_err(io::Error::other)?;
+        let socket = NativeSocket { ws: Rc::new(RefCell::new(ws)) };
+        socket.write(&Frame::Hello { room: room.to_string(), peer: id.to_string() })?;
+        loop {
+            match socket.read(Duration::from_secs(1))? {
+                Some(Frame::Welcome { .. }) => break,
+                Some(Frame::Error { reason }) => return Err(io::Error::other(reason)),
+                _ => {},
+            }
+        }
+        let peer = RelayPeer::new(id, socket.clone());
+        Ok(Client { socket, peer })
+    }
+
+    pub fn peer(&self) -> &RelayPeer {
+        &self.peer
+    }
+
+    /// Handles frames for up to `timeout`, returning after the
+    /// first one. Once the socket fails, its connections are
+    /// closed and the error is returned.
+    pub fn poll(&self, timeout: Duration) -> io::Result<()> {
+        match self.socket.read(timeout) {
+            Ok(Some(frame)) => {
+                self.peer.receive(frame);
+                Ok(())
+            },
+            Ok(None) => Ok(()),
+            Err(err) => {
+                self.peer.disconnected();
+                Err(err)
+            },
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! A peer's end of the relay, as a `Transport`. It knows the
+//! frames but not the socket, so the browser and native
+//! clients share it and only differ in how frames move.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+
+use super::Frame;
+use crate::net::{Message, Metadata};
+use crate::transport::{Connection, Transport};
+
+type Handler<T> = Rc<dyn Fn(T)>;
+
+/// The socket to the relay server that a `RelayPeer` sends on.
+pub trait Socket: 'static {
+    /// Sends a frame, or queues it until the socket opens.
+    fn send(&self, frame: &Frame);
+
+    /// Closes the socket.
+    fn close(&self);
+}
+
+#[derive(Default)]
+struct Handlers {
+    metadata: Metadata,
+    on_open: Vec<Handler<()>>,
+    on_data: Vec<Handler<Message>>,
+    on_close: Vec<Handler<()>>,
+}
+
+struct Inner {
+    socket: Box<dyn Socket>,
+    id: String,
+    on_connection: RefCell<Vec<Handler<RelayConnection>>>,
+    on_disconnect: RefCell<Vec<Handler<()>>>,
+    on_error: RefCell<Vec<Handler<String>>>,
+    /// Handlers of each open or opening connection, by peer ID.
+    conns: RefCell<HashMap<String, Handlers>>,
+}
+
+/// This peer's end of a socket to a relay server.
+#[derive(Clone)]
+pub struct RelayPeer {
+    inner: Rc<Inner>,
+}
+
+/// A `RelayPeer` that doesn't keep it alive,
+/// for the socket's own callbacks to hold.
+#[derive(Clone)]
+pub struct WeakRelayPeer {
+    inner: Weak<Inner>,
+}
+
+/// A connection to another peer through the relay.
+#[derive(Clone)]
+pub struct RelayConnection {
+    relay: RelayPeer,
+    peer: String,
+}
+
+impl RelayPeer {
+    /// Becomes peer `id` on `socket`, which should
+    /// already have sent, or be about to send, `Hello`.
+    pub fn new(id: &str, socket: impl Socket) -> Self {
+        RelayPeer {
+            inner: Rc::new(Inner {
+                socket: Box::new(socket),
+                id: id.to_string(),
+                on_connection: RefCell::new(Vec::new()),
+                on_disconnect: RefCell::new(Vec::new()),
+                on_error: RefCell::new(Vec::new()),
+                conns: RefCell::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub fn downgrade(&self) -> WeakRelayPeer {
+        WeakRelayPeer { inner: Rc::downgrade(&self.inner) }
+    }
+
+    /// Called when the socket to the relay closes.
+    pub fn on_disconnect(&self, f: impl Fn() + 'static) {
+        self.inner.on_disconnect.borrow_mut().push(Rc::new(move |()| f()));
+    }
+
+    /// Called with frames the relay refused, and with
+    /// messages from other peers that couldn't be read.
+    pub fn on_error(&self, f: impl Fn(String) + 'static) {
+        self.inner.on_error.borrow_mut().push(Rc::new(f));
+    }
+
+    fn error(&self, reason: String) {
+        let handlers = self.inner.on_error.borrow().clone();
+        for handler in handlers {
+            handler(reason.clone());
+        }
+    }
+
+    fn handlers<T: Clone>(&self, peer: &str, f: impl FnOnce(&Handlers) -> &Vec<T>) -> Vec<T> {
+        self.inner.conns.borrow().get(peer).map(|handlers| f(handlers).clone()).unwrap_or_default()
+    }
+
+    /// Handles a frame that arrived on the socket.
+    pub fn receive(&self, frame: Frame) {
+        match frame {
+            Frame::Open { peer, metadata } => {
+                let incoming = !self.inner.conns.borrow().contains_key(&peer);
+                if incoming {
+                    self.inner.conns.borrow_mut().insert(peer.clone(), Handlers { metadata, ..Default::default() });
+                    let conn = RelayConnection { relay: self.clone(), peer: peer.clone() };
+                    let handlers = self.inner.on_connection.borrow().clone();
+                    for handler in handlers {
+                        handler(conn.clone());
+                    }
+                }
+                for handler in self.handlers(&peer, |handlers| &handlers.on_open) {
+                    handler(());
+                }
+            },
+            Frame::Data { peer, message } => match serde_json::from_value::<Message>(message) {
+                Ok(message) => {
+                    for handler in self.handlers(&peer, |handlers| &handlers.on_data) {
+                        handler(message.clone());
+                    }
+                },
+                Err(err) => self.error(format!("error parsing message from {}: {}", peer, err)),
+            },
+            Frame::Close { peer } => self.closed(&peer),
+            Frame::Error { reason } => self.error(format!("relay: {}", reason)),
+            Frame::Welcome { .. } | Frame::Hello { .. } | Frame::Connect { .. } => {},
+        }
+    }
+
+    /// Handles the socket closing, which closes every connection.
+    pub fn disconnected(&self) {
+        let peers: Vec<String> = self.inner.conns.borrow().keys().cloned().collect();
+        for peer in peers {
+            self.closed(&peer);
+        }
+        let handlers = self.inner.on_disconnect.borrow().clone();
+        for handler in handlers {
+            handler(());
+        }
+    }
+
+    /// Forgets the connection with `peer` and tells its handlers.
+    fn closed(&self, peer: &str) {
+        let handlers = self.inner.conns.borrow_mut().remove(peer);
+        for handler in handlers.map(|handlers| handlers.on_close).unwrap_or_default() {
+            handler(());
+        }
+    }
+}
+
+impl WeakRelayPeer {
+    pub fn upgrade(&self) -> Option<RelayPeer> {
+        self.inner.upgrade().map(|inner| RelayPeer { inner })
+    }
+}
+
+impl Connection for RelayConnection {
+    fn peer(&self) -> String {
+        self.peer.clone()
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.relay.inner.conns.borrow().get(&self.peer).map(|handlers| handlers.metadata.clone()).unwrap_or_default()
+    }
+
+    fn send(&self, message: &Message) {
+        let message = serde_json::to_value(message).expect("messages serialize");
+        self.relay.inner.socket.send(&Frame::Data { peer: self.peer.clone(), message });
+    }
+
+    fn close(&self) {
+        self.relay.inner.socket.send(&Frame::Close { peer: self.peer.clone() });
+        self.relay.closed(&self.peer);
+    }
+
+    fn on_open(&self, f: impl Fn() + 'static) {
+        if let Some(handlers) = self.relay.inner.conns.borrow_mut().get_mut(&self.peer) {
+            handlers.on_open.push(Rc::new(move |()| f()));
+        }
+    }
+
+    fn on_data(&self, f: impl Fn(Message) + 'static) {
+        if let Some(handlers) = self.relay.inner.conns.borrow_mut().get_mut(&self.peer) {
+            handlers.on_data.push(Rc::new(f));
+        }
+    }
+
+    fn on_close(&self, f: impl Fn() + 'static) {
+        if let Some(handlers) = self.relay.inner.conns.borrow_mut().get_mut(&self.peer) {
+            handlers.on_close.push(Rc::new(move |()| f()));
+        }
+    }
+}
+
+impl Transport for RelayPeer {
+    type Connection = RelayConnection;
+
+    fn id(&self) -> String {
+        self.inner.id.clone()
+    }
+
+    fn connect(&self, id: &str, metadata: &Metadata) -> RelayConnection {
+        self.inner.conns.borrow_mut().insert(id.to_string(), Handlers { metadata: metadata.clone(), ..Default::default() });
+        self.inner.socket.send(&Frame::Connect { peer: id.to_string(), metadata: metadata.clone() });
+        RelayConnection { relay: self.clone(), peer: id.to_string() }
+    }
+
+    fn on_connection(&self, f: impl Fn(RelayConnection) + 'static) {
+        self.inner.on_connection.borrow_mut().push(Rc::new(f));
+    }
+
+    fn destroy(&self) {
+        self.inner.socket.close();
+    }
+}
@@ -0,0 +1,113 @@
+//! A blocking WebSocket server around `Relay`.
+//!
+//! Each socket gets a thread that reads frames and passes them
+//! to one relay thread, which owns the `Relay` and hands the
+//! resulting frames back to the socket threads to write.
+
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use tungstenite::{Message as WsMessage, WebSocket};
+
+use super::{Frame, Relay, SocketId};
+
+/// How long a socket thread waits for a frame
+/// before checking for frames to write.
+const POLL: Duration = Duration::from_millis(20);
+
+/// How long to wait after failing to accept a socket, as the
+/// next try would likely fail too if we're out of file handles.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+enum Event {
+    Open(SocketId, Sender<Frame>),
+    Frame(SocketId, Frame),
+    Closed(SocketId),
+}
+
+/// Accepts WebSocket connections on `listener` and relays
+/// frames between them, forever. A socket that can't be
+/// accepted is skipped, leaving the others be.
+pub fn serve(listener: TcpListener) {
+    let (events, inbox) = mpsc::channel();
+    thread::spawn(move || run(inbox));
+
+    for (id, stream) in listener.incoming().enumerate() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("accepting socket {}: {}", id, err);
+                thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            },
+        };
+        let events = events.clone();
+        thread::spawn(move || {
+            if let Err(err) = socket(id, stream, events) {
+                eprintln!("socket {}: {}", id, err);
+            }
+        });
+    }
+}
+
+fn run(inbox: Receiver<Event>) {
+    let mut relay = Relay::new();
+    let mut sockets = std::collections::HashMap::new();
+    for event in inbox {
+        let outbox = match event {
+            Event::Open(id, sender) => {
+                sockets.insert(id, sender);
+                Vec::new()
+            },
+            Event::Frame(id, frame) => relay.handle(id, frame),
+            Event::Closed(id) => {
+                sockets.remove(&id);
+                relay.disconnect(id)
+            },
+        };
+        for (id, frame) in outbox {
+            if let Some(sender) = sockets.get(&id) {
+                let _ = sender.send(frame);
+            }
+        }
+    }
+}
+
+fn socket(id: SocketId, stream: TcpStream, events: Sender<Event>) -> io::Result<()> {
+    let mut ws = tungstenite::accept(stream).map_err(io::Error::other)?;
+    ws.get_ref().set_read_timeout(Some(POLL))?;
+
+    let (sender, outbox) = mpsc::channel();
+    let _ = events.send(Event::Open(id, sender));
+    let result = pump(id, &mut ws, &events, &outbox);
+    let _ = events.send(Event::Closed(id));
+    result
+}
+
+fn send(ws: &mut WebSocket<TcpStream>, frame: &Frame) -> io::Result<()> {
+    let text = serde_json::to_string(frame).expect("frames serialize");
+    ws.send(WsMessage::Text(text)).map_err(io::Error::other)
+}
+
+fn pump(id: SocketId, ws: &mut WebSocket<TcpStream>, events: &Sender<Event>, outbox: &Receiver<Frame>) -> io::Result<()> {
+    loop {
+        for frame in outbox.try_iter() {
+            send(ws, &frame)?;
+        }
+
+        match ws.read() {
+            Ok(WsMessage::Text(text)) => match serde_json::from_str(&text) {
+                Ok(frame) => { let _ = events.send(Event::Frame(id, frame)); },
+                Err(err) => send(ws, &Frame::Error { reason: format!("bad frame: {}", err) })?,
+            },
+            Ok(WsMessage::Close(_)) => return Ok(()),
+            Ok(_) => {},
+            Err(tungstenite::Error::Io(err)) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {},
+            Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => return Ok(()),
+            Err(err) => return Err(io::Error::other(err)),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! A peer's view of the room, built up from the messages the host sends.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::game::{self, Phase};
+use crate::net::{self, Message};
+
+/// How many lines of chat a room keeps.
+pub const CHAT_SCROLLBACK: usize = 200;
+
+/// A line in the room's chat.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatLine {
+    /// When it was said, in host time.
+    pub time: u64,
+    /// Who said it, or `None` for the room's own notices
+    /// about players coming and going and the round.
+    pub name: Option<String>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Room {
+    /// Player names by ID.
+    pub players: HashMap<String, String>,
+    pub scores: HashMap<String, u32>,
+    pub bids: HashMap<String, game::Bid>,
+    pub phase: Phase,
+
+    /// The bidders still to demonstrate after the current one, in order.
+    pub queue: Vec<String>,
+
+    /// Index into the board's targets of this round's target.
+    pub target: Option<usize>,
+
+    /// This peer's player ID.
+    pub me: String,
+
+    /// What the host agreed to speak with us, once it has.
+    pub welcome: Option<net::WelcomeMessage>,
+
+    /// The outcome of the last demonstration.
+    pub verdict: Option<net::VerdictMessage>,
+
+    /// Why the host rejected our last message.
+    pub error: Option<String>,
+
+    /// The last `CHAT_SCROLLBACK` lines of chat, oldest first.
+    pub chat: VecDeque<ChatLine>,
+}
+
+impl Room {
+    pub fn new(me: &str) -> Self {
+        Room { me: me.to_string(), ..Default::default() }
+    }
+
+    /// Updates the room with a message from the host,
+    /// received at host time `now`. Messages that don't
+    /// concern the room, like demo moves, are ignored.
+    pub fn apply(&mut self, message: &Message, now: u64) {
+        match message {
+            Message::Welcome(msg) => {
+                self.welcome = Some(msg.clone());
+            },
+            Message::BoardState(state) => {
+                self.target = state.target;
+                self.bids.clear();
+                self.queue.clear();
+                self.error = None;
+                self.phase = if state.target.is_some() { Phase::Revealed } else { Phase::Idle };
+            },
+            Message::PlayerJoin(msg) => {
+                // a list with us in it, or before we know
+                // anyone, is the host catching us up
+                let catching_up = self.players.is_empty() || msg.ids.contains(&self.me);
+                for ((id, name), score) in msg.ids.iter().zip(&msg.names).zip(&msg.scores) {
+                    if !catching_up && !self.players.contains_key(id) {
+                        self.notice(now, format!("{} joined.", name));
+                    }
+                    self.players.insert(id.clone(), name.clone());
+                    self.scores.insert(id.clone(), *score);
+                }
+            },
+            Message::PlayerLeave(msg) => {
+                if msg.id == self.me {
+                    self.error = msg.reason.clone();
+                }
+                if let Some(name) = self.players.remove(&msg.id) {
+                    let text = if msg.reason.is_some() { "was removed by the host" } else { "left" };
+                    self.notice(now, format!("{} {}.", name, text));
+                }
+                self.scores.remove(&msg.id);
+                self.bids.remove(&msg.id);
+                self.queue.retain(|id| *id != msg.id);
+            },
+            Message::StartBid(msg) => {
+                self.phase = Phase::Bidding { end_time: msg.end_time };
+                self.notice(now, "Bidding is open.".to_string());
+            },
+            Message::UpdateBid(msg) => {
+                let timestamp = msg.timestamp.unwrap_or(now);
+                self.bids.insert(msg.player.clone(), game::Bid { timestamp, bid: msg.bid, player: msg.player.clone() });
+            },
+            Message::StartEval(msg) => {
+                let bid = self.bids.get(&msg.player).map(|bid| bid.bid).unwrap_or(0);
+                self.phase = Phase::Evaluating { player: msg.player.clone(), bid };
+                self.queue = msg.queue.clone();
+                self.notice(now, format!("{} is showing their {} moves.", self.name(&msg.player), bid));
+            },
+            Message::Verdict(msg) => {
+                self.verdict = Some(msg.clone());
+            },
+            Message::EndRound(msg) => {
+                if let Some(winner) = &msg.winner {
+                    self.scores.insert(winner.clone(), msg.score);
+                }
+                let text = match &msg.winner {
+                    Some(winner) => format!("{} won the round.", self.name(winner)),
+                    None => "Nobody solved it this round.".to_string(),
+                };
+                self.notice(now, text);
+                self.bids.clear();
+                self.queue.clear();
+                self.phase = Phase::Idle;
+            },
+            Message::Chat(msg) => {
+                self.say(ChatLine { time: msg.time, name: Some(msg.name.clone()), text: msg.text.clone() });
+            },
+            Message::Error(msg) => {
+                self.error = Some(msg.reason.clone());
+            },
+            _ => {},
+        }
+    }
+
+    /// A player's name, or their ID if we don't know it.
+    pub fn name(&self, id: &str) -> String {
+        self.players.get(id).cloned().unwrap_or_else(|| id.to_string())
+    }
+
+    fn notice(&mut self, now: u64, text: String) {
+        self.say(ChatLine { time: now, name: None, text });
+    }
+
+    fn say(&mut self, line: ChatLine) {
+        if self.chat.len() == CHAT_SCROLLBACK {
+            self.chat.pop_front();
+        }
+        self.chat.push_back(line);
+    }
+
+    /// Forgets the host, or the peer `gone` that was to take
+    /// over from it, after the connection to it was lost. Returns
+    /// who should host now: the player with the lowest ID, so
+    /// every client picks the same one without asking.
+    pub fn elect(&mut self, gone: &str) -> Option<String> {
+        for id in [game::HOST_ID, gone] {
+            self.players.remove(id);
+            self.scores.remove(id);
+        }
+        self.players.keys().min().cloned()
+    }
+
+    /// Whether this peer is playing, rather than watching.
+    pub fn playing(&self) -> bool {
+        self.players.contains_key(&self.me)
+    }
+
+    /// Whether this peer is demonstrating their solution.
+    pub fn demonstrating(&self) -> bool {
+        matches!(&self.phase, Phase::Evaluating { player, .. } if *player == self.me)
+    }
+
+    /// Whether someone else is demonstrating their solution.
+    pub fn spectating(&self) -> bool {
+        matches!(&self.phase, Phase::Evaluating { player, .. } if *player != self.me)
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+
+use crate::board::{Board, Direction, RobotPositions, Target};
+
+/// A search node. Nodes are stored in BFS order,
+/// so the node list doubles as the search queue.
+struct Node {
+    positions: RobotPositions,
+    depth: usize,
+
+    /// Index of the parent node and the move taken from it.
+    parent: Option<(usize, (usize, Direction))>,
+}
+
+/// Returns a key identifying equivalent positions.
+///
+/// Without diagonals, robots that can't score the target
+/// are interchangeable, so their positions are sorted.
+fn canonical(board: &Board, positions: RobotPositions, target: &Target) -> RobotPositions {
+    if !board.diagonals.is_empty() {
+        return positions;
+    }
+    let mut key = positions;
+    match target.robot {
+        Some(robot) => {
+            key.swap(0, robot);
+            key[1..].sort_unstable();
+        },
+        None => key.sort_unstable(),
+    }
+    key
+}
+
+/// Why `solve_within` found no solution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unsolved {
+    /// Every sequence of up to `max_moves` moves was tried.
+    TooLong,
+
+    /// The search gave up after reaching `max_nodes` positions.
+    GaveUp,
+}
+
+/// Finds a shortest move sequence, of at most `max_moves` moves,
+/// that leaves a robot accepted by `target` on the target tile.
+///
+/// Returns `None` if no such sequence exists.
+pub fn solve(board: &Board, positions: RobotPositions, target: &Target, max_moves: usize) -> Option<Vec<(usize, Direction)>> {
+    solve_within(board, positions, target, max_moves, usize::MAX).ok()
+}
+
+/// Like `solve`, but gives up once `max_nodes` positions have been
+/// reached, bounding the time and memory a long search takes.
+pub fn solve_within(board: &Board, positions: RobotPositions, target: &Target, max_moves: usize, max_nodes: usize)
+    -> Result<Vec<(usize, Direction)>, Unsolved>
+{
+    let mut nodes = vec![Node { positions, depth: 0, parent: None }];
+    let mut visited: HashSet<RobotPositions> = HashSet::new();
+    visited.insert(canonical(board, positions, target));
+
+    let mut next = 0;
+    while next < nodes.len() {
+        let index = next;
+        next += 1;
+
+        let (current, depth) = (nodes[index].positions, nodes[index].depth);
+        if depth >= max_moves {
+            // nodes are in BFS order, so every later node is at least as deep
+            break;
+        }
+
+        for robot in 0..current.len() {
+            for direction in Direction::ALL {
+                let moved = board.move_robot(current, robot, direction);
+                if moved == current || !visited.insert(canonical(board, moved, target)) {
+                    continue;
+                }
+
+                nodes.push(Node { positions: moved, depth: depth + 1, parent: Some((index, (robot, direction))) });
+                if moved.iter().enumerate().any(|(robot, &pos)| pos == target.tile && target.accepts(robot)) {
+                    return Ok(path(&nodes, nodes.len() - 1));
+                }
+                if nodes.len() >= max_nodes {
+                    return Err(Unsolved::GaveUp);
+                }
+            }
+        }
+    }
+
+    Err(Unsolved::TooLong)
+}
+
+/// Returns the moves leading from the root to the given node.
+fn path(nodes: &[Node], mut index: usize) -> Vec<(usize, Direction)> {
+    let mut moves = Vec::new();
+    while let Some((parent, mv)) = nodes[index].parent {
+        moves.push(mv);
+        index = parent;
+    }
+    moves.reverse();
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use super::*;
+    use crate::board::{Diagonal, Orientation, Symbol};
+
+    /// A 6x6 board without walls, with robot 0 in the top
+    /// left corner and the others along the bottom row.
+    fn open_board() -> Board {
+        Board {
+            width: 6,
+            horizontal_walls: vec![false; 6 * 5],
+            vertical_walls: vec![false; 5 * 6],
+            center: false,
+            initial_positions: [0, 31, 32, 33, 34],
+            targets: Vec::new(),
+            diagonals: Vec::new(),
+            table: OnceLock::new()
+        }
+    }
+
+    fn target(tile: usize, robot: Option<usize>) -> Target {
+        Target { tile, robot, symbol: if robot.is_some() { Symbol::Moon } else { Symbol::Vortex } }
+    }
+
+    #[test]
+    fn finds_shortest_solutions() {
+        let board = open_board();
+        for (target, length) in [
+            (target(5, Some(0)), 1),
+            // down first stops in the corner, left of the other robots
+            (target(35, Some(0)), 2),
+            // any robot will do, and one is next to it
+            (target(35, None), 1),
+        ] {
+            let moves = solve(&board, board.initial_positions, &target, 10).expect("target is reachable");
+            assert_eq!(moves.len(), length, "{:?}", target);
+            assert!(board.is_solution(board.initial_positions, &target, &moves));
+        }
+    }
+
+    #[test]
+    fn stops_at_the_limits() {
+        let board = open_board();
+        let target = target(35, Some(0));
+        assert!(solve(&board, board.initial_positions, &target, 1).is_none());
+        assert_eq!(solve_within(&board, board.initial_positions, &target, 1, usize::MAX).err(), Some(Unsolved::TooLong));
+        assert_eq!(solve_within(&board, board.initial_positions, &target, 10, 2).err(), Some(Unsolved::GaveUp));
+    }
+
+    #[test]
+    fn only_interchangeable_robots_share_a_key() {
+        let mut board = open_board();
+        let positions = [0, 31, 32, 33, 34];
+        let others_swapped = [0, 34, 32, 33, 31];
+        let target_swapped = [31, 0, 32, 33, 34];
+
+        let red = target(35, Some(0));
+        assert_eq!(canonical(&board, positions, &red), canonical(&board, others_swapped, &red));
+        assert_ne!(canonical(&board, positions, &red), canonical(&board, target_swapped, &red));
+
+        let vortex = target(35, None);
+        assert_eq!(canonical(&board, positions, &vortex), canonical(&board, target_swapped, &vortex));
+
+        // diagonals let robots of their color through, so colors matter
+        board.diagonals.push(Diagonal { tile: 14, robot: 1, orientation: Orientation::Slash });
+        assert_ne!(canonical(&board, positions, &red), canonical(&board, others_swapped, &red));
+    }
+}
@@ -0,0 +1,56 @@
+//! How peers reach each other.
+//!
+//! The game only needs to open connections, send `Message`s
+//! and hear about connections, data and disconnects. PeerJS
+//! provides this in the browser, and `loopback` provides it
+//! in memory for tests.
+
+use crate::net::{Message, Metadata};
+
+pub mod loopback;
+
+/// A connection to one other peer.
+pub trait Connection: Clone + 'static {
+    /// The other peer's ID.
+    fn peer(&self) -> String;
+
+    /// What the connecting peer said about itself.
+    fn metadata(&self) -> Metadata;
+
+    fn send(&self, message: &Message);
+
+    /// Sends a message to each of `conns`.
+    fn broadcast<'a>(conns: impl IntoIterator<Item = &'a Self>, message: &Message) {
+        for conn in conns {
+            conn.send(message);
+        }
+    }
+
+    fn close(&self);
+
+    /// Called once the connection is ready for sending.
+    fn on_open(&self, f: impl Fn() + 'static);
+
+    /// Called for each message received.
+    fn on_data(&self, f: impl Fn(Message) + 'static);
+
+    /// Called once when either side closes the connection.
+    fn on_close(&self, f: impl Fn() + 'static);
+}
+
+/// This peer's presence on the network.
+pub trait Transport: Clone + 'static {
+    type Connection: Connection;
+
+    /// This peer's ID, which others connect to.
+    fn id(&self) -> String;
+
+    /// Opens a connection to the peer with ID `id`.
+    fn connect(&self, id: &str, metadata: &Metadata) -> Self::Connection;
+
+    /// Called for each connection another peer opens to this one.
+    fn on_connection(&self, f: impl Fn(Self::Connection) + 'static);
+
+    /// Closes every connection and leaves the network.
+    fn destroy(&self);
+}
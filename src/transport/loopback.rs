@@ -0,0 +1,364 @@
+//! An in-memory network for running whole games in one process.
+//!
+//! Nothing is delivered until `Network::run` is called, which
+//! fires queued events in order until the network is quiet.
+//! Handlers can send more messages while it runs.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+
+use super::{Connection, Transport};
+use crate::net::{Message, Metadata};
+
+type Handler<T> = Rc<dyn Fn(T)>;
+
+#[allow(clippy::large_enum_variant)]
+enum Event {
+    Connection { peer: String, conn: usize },
+    Open(usize),
+    Data(usize, Message),
+    Close(usize),
+}
+
+struct ConnState {
+    /// The peer holding this end.
+    local: String,
+    /// The peer at the other end.
+    remote: String,
+    /// This connection's other end.
+    other: usize,
+    metadata: Metadata,
+    open: bool,
+    on_open: Vec<Handler<()>>,
+    on_data: Vec<Handler<Message>>,
+    on_close: Vec<Handler<()>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Connection handlers of each peer on the network.
+    peers: HashMap<String, Vec<Handler<LoopbackConnection>>>,
+    conns: Vec<ConnState>,
+    queue: VecDeque<Event>,
+
+    /// Peers whose events are held back, and the events.
+    held: HashSet<String>,
+    holding: VecDeque<Event>,
+}
+
+impl Inner {
+    /// The peer an event is for.
+    fn recipient<'a>(&'a self, event: &'a Event) -> &'a str {
+        match event {
+            Event::Connection { peer, .. } => peer,
+            Event::Open(conn) | Event::Data(conn, _) | Event::Close(conn) => &self.conns[*conn].local,
+        }
+    }
+}
+
+/// The shared network that loopback peers live on.
+#[derive(Clone, Default)]
+pub struct Network {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Joins the network as the peer with ID `id`.
+    pub fn peer(&self, id: &str) -> LoopbackPeer {
+        self.inner.borrow_mut().peers.insert(id.to_string(), Vec::new());
+        LoopbackPeer { network: self.clone(), id: id.to_string() }
+    }
+
+    /// Delivers queued events until there are none left.
+    /// Returns how many were delivered.
+    pub fn run(&self) -> usize {
+        let mut delivered = 0;
+        while let Some(event) = self.next_event() {
+            self.fire(event);
+            delivered += 1;
+        }
+        delivered
+    }
+
+    /// Holds back every event for the peer `id` until `release`,
+    /// as if it were slow to hear about anything. Others still
+    /// see their ends of its connections open and close.
+    pub fn hold(&self, id: &str) {
+        self.inner.borrow_mut().held.insert(id.to_string());
+    }
+
+    /// Queues the events held back for the peer `id`, in order.
+    pub fn release(&self, id: &str) {
+        let mut inner = self.inner.borrow_mut();
+        inner.held.remove(id);
+        let holding = std::mem::take(&mut inner.holding);
+        for event in holding {
+            if inner.held.contains(inner.recipient(&event)) {
+                inner.holding.push_back(event);
+            } else {
+                inner.queue.push_back(event);
+            }
+        }
+    }
+
+    fn next_event(&self) -> Option<Event> {
+        let mut inner = self.inner.borrow_mut();
+        while let Some(event) = inner.queue.pop_front() {
+            if !inner.held.contains(inner.recipient(&event)) {
+                return Some(event);
+            }
+            inner.holding.push_back(event);
+        }
+        None
+    }
+
+    fn push(&self, event: Event) {
+        self.inner.borrow_mut().queue.push_back(event);
+    }
+
+    /// Calls the handlers for an event. Handlers are cloned
+    /// out first, so they can use the network themselves.
+    fn fire(&self, event: Event) {
+        match event {
+            Event::Connection { peer, conn } => {
+                let handlers = self.inner.borrow().peers.get(&peer).cloned().unwrap_or_default();
+                for handler in handlers {
+                    handler(LoopbackConnection { network: self.clone(), index: conn });
+                }
+            },
+            Event::Open(conn) => {
+                let handlers = {
+                    let mut inner = self.inner.borrow_mut();
+                    inner.conns[conn].open = true;
+                    inner.conns[conn].on_open.clone()
+                };
+                for handler in handlers {
+                    handler(());
+                }
+            },
+            Event::Data(conn, message) => {
+                let handlers = {
+                    let inner = self.inner.borrow();
+                    if !inner.conns[conn].open {
+                        return;
+                    }
+                    inner.conns[conn].on_data.clone()
+                };
+                for handler in handlers {
+                    handler(message.clone());
+                }
+            },
+            Event::Close(conn) => {
+                let handlers = {
+                    let mut inner = self.inner.borrow_mut();
+                    inner.conns[conn].open = false;
+                    std::mem::take(&mut inner.conns[conn].on_close)
+                };
+                for handler in handlers {
+                    handler(());
+                }
+            },
+        }
+    }
+
+    /// Closes both ends of a connection, if still open. This end
+    /// stops sending at once, but the other end still gets what
+    /// was sent before it hears of the close.
+    fn close(&self, conn: usize) {
+        let mut inner = self.inner.borrow_mut();
+        let other = inner.conns[conn].other;
+        for end in [conn, other] {
+            if inner.conns[end].open {
+                inner.queue.push_back(Event::Close(end));
+            }
+        }
+        inner.conns[conn].open = false;
+    }
+}
+
+/// A peer on a loopback `Network`.
+#[derive(Clone)]
+pub struct LoopbackPeer {
+    network: Network,
+    id: String,
+}
+
+/// One end of a loopback connection.
+#[derive(Clone)]
+pub struct LoopbackConnection {
+    network: Network,
+    index: usize,
+}
+
+impl LoopbackConnection {
+    fn with<R>(&self, f: impl FnOnce(&mut ConnState) -> R) -> R {
+        f(&mut self.network.inner.borrow_mut().conns[self.index])
+    }
+}
+
+impl Connection for LoopbackConnection {
+    fn peer(&self) -> String {
+        self.with(|conn| conn.remote.clone())
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.with(|conn| conn.metadata.clone())
+    }
+
+    fn send(&self, message: &Message) {
+        let (open, other) = self.with(|conn| (conn.open, conn.other));
+        if open {
+            self.network.push(Event::Data(other, message.clone()));
+        }
+    }
+
+    fn close(&self) {
+        self.network.close(self.index);
+    }
+
+    fn on_open(&self, f: impl Fn() + 'static) {
+        self.with(|conn| conn.on_open.push(Rc::new(move |()| f())));
+    }
+
+    fn on_data(&self, f: impl Fn(Message) + 'static) {
+        self.with(|conn| conn.on_data.push(Rc::new(f)));
+    }
+
+    fn on_close(&self, f: impl Fn() + 'static) {
+        self.with(|conn| conn.on_close.push(Rc::new(move |()| f())));
+    }
+}
+
+impl Transport for LoopbackPeer {
+    type Connection = LoopbackConnection;
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// Connecting to a peer that isn't on the network
+    /// gives a connection that closes straight away.
+    fn connect(&self, id: &str, metadata: &Metadata) -> LoopbackConnection {
+        let mut inner = self.network.inner.borrow_mut();
+        let ours = inner.conns.len();
+        let theirs = ours + 1;
+        for (index, local, remote, other) in [(ours, &self.id, id, theirs), (theirs, &id.to_string(), self.id.as_str(), ours)] {
+            debug_assert_eq!(inner.conns.len(), index);
+            inner.conns.push(ConnState {
+                local: local.clone(),
+                remote: remote.to_string(),
+                other,
+                metadata: metadata.clone(),
+                open: false,
+                on_open: Vec::new(),
+                on_data: Vec::new(),
+                on_close: Vec::new(),
+            });
+        }
+
+        if inner.peers.contains_key(id) {
+            inner.queue.push_back(Event::Connection { peer: id.to_string(), conn: theirs });
+            inner.queue.push_back(Event::Open(theirs));
+            inner.queue.push_back(Event::Open(ours));
+        } else {
+            inner.queue.push_back(Event::Close(ours));
+        }
+        LoopbackConnection { network: self.network.clone(), index: ours }
+    }
+
+    fn on_connection(&self, f: impl Fn(LoopbackConnection) + 'static) {
+        let mut inner = self.network.inner.borrow_mut();
+        if let Some(handlers) = inner.peers.get_mut(&self.id) {
+            handlers.push(Rc::new(f));
+        }
+    }
+
+    fn destroy(&self) {
+        let conns: Vec<usize> = {
+            let mut inner = self.network.inner.borrow_mut();
+            inner.peers.remove(&self.id);
+            (0..inner.conns.len()).filter(|&i| inner.conns[i].local == self.id).collect()
+        };
+        for conn in conns {
+            self.network.close(conn);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::PingMessage;
+
+    #[test]
+    fn delivers_in_order() {
+        let network = Network::new();
+        let host = network.peer("host");
+        let client = network.peer("client");
+
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let log = received.clone();
+        host.on_connection(move |conn| {
+            let log = log.clone();
+            assert_eq!(conn.peer(), "client");
+            assert_eq!(conn.metadata().name, "Alice");
+            conn.on_data(move |message| {
+                if let Message::Ping(ping) = message {
+                    log.borrow_mut().push(ping.sent);
+                }
+            });
+        });
+
+        let conn = client.connect("host", &Metadata { name: "Alice".into(), ..Default::default() });
+        // nothing can be sent before the connection opens
+        conn.send(&Message::Ping(PingMessage { sent: 0 }));
+        network.run();
+        for sent in 1..=3 {
+            conn.send(&Message::Ping(PingMessage { sent }));
+        }
+        network.run();
+        assert_eq!(*received.borrow(), vec![1, 2, 3]);
+
+        // what was sent before closing still arrives, but nothing after
+        conn.send(&Message::Ping(PingMessage { sent: 4 }));
+        conn.close();
+        conn.send(&Message::Ping(PingMessage { sent: 5 }));
+        network.run();
+        assert_eq!(*received.borrow(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn close_reaches_both_ends() {
+        let network = Network::new();
+        let host = network.peer("host");
+        let client = network.peer("client");
+
+        let closed = Rc::new(RefCell::new(0));
+        let count = closed.clone();
+        host.on_connection(move |conn| {
+            let count = count.clone();
+            conn.on_close(move || *count.borrow_mut() += 1);
+        });
+        let conn = client.connect("host", &Metadata::default());
+        let count = closed.clone();
+        conn.on_close(move || *count.borrow_mut() += 1);
+        network.run();
+
+        host.destroy();
+        conn.close();
+        network.run();
+        assert_eq!(*closed.borrow(), 2);
+
+        // connecting to a peer that's gone fails
+        let conn = client.connect("host", &Metadata::default());
+        let count = closed.clone();
+        conn.on_close(move || *count.borrow_mut() += 1);
+        network.run();
+        assert_eq!(*closed.borrow(), 3);
+    }
+}
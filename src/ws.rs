@@ -0,0 +1,66 @@
+//! The browser's end of the WebSocket relay in `robots::relay`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use robots::relay::{Frame, RelayPeer, Socket};
+use wasm_bindgen::{JsCast, JsValue, prelude::Closure};
+use web_sys::{console, MessageEvent, WebSocket};
+
+struct BrowserSocket {
+    socket: WebSocket,
+    /// Frames sent before the socket opened, starting with `Hello`.
+    pending: Rc<RefCell<Vec<String>>>,
+}
+
+impl Socket for BrowserSocket {
+    fn send(&self, frame: &Frame) {
+        let text = serde_json::to_string(frame).expect("frames serialize");
+        if self.socket.ready_state() == WebSocket::OPEN {
+            let _ = self.socket.send_with_str(&text);
+        } else {
+            self.pending.borrow_mut().push(text);
+        }
+    }
+
+    fn close(&self) {
+        let _ = self.socket.close();
+    }
+}
+
+/// Connects to the relay at `url` as peer `id` in `room`.
+pub fn connect(url: &str, room: &str, id: &str) -> Result<RelayPeer, JsValue> {
+    let socket = WebSocket::new(url)?;
+    let hello = serde_json::to_string(&Frame::Hello { room: room.to_string(), peer: id.to_string() })
+        .expect("frames serialize");
+    let pending = Rc::new(RefCell::new(vec![hello]));
+    let relay = RelayPeer::new(id, BrowserSocket { socket: socket.clone(), pending: pending.clone() });
+    relay.on_error(|reason| console::error_1(&reason.into()));
+
+    let ws = socket.clone();
+    socket.set_onopen(Some(Closure::<dyn Fn()>::new(move || {
+        for frame in pending.take() {
+            let _ = ws.send_with_str(&frame);
+        }
+    }).into_js_value().unchecked_ref()));
+
+    let weak = relay.downgrade();
+    socket.set_onmessage(Some(Closure::<dyn Fn(MessageEvent)>::new(move |event: MessageEvent| {
+        let (Some(relay), Some(text)) = (weak.upgrade(), event.data().as_string()) else {
+            return;
+        };
+        match serde_json::from_str(&text) {
+            Ok(frame) => relay.receive(frame),
+            Err(err) => console::error_1(&format!("bad frame from relay: {}", err).into()),
+        }
+    }).into_js_value().unchecked_ref()));
+
+    let weak = relay.downgrade();
+    socket.set_onclose(Some(Closure::<dyn Fn()>::new(move || {
+        if let Some(relay) = weak.upgrade() {
+            relay.disconnected();
+        }
+    }).into_js_value().unchecked_ref()));
+
+    Ok(relay)
+}
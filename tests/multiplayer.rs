@@ -0,0 +1,559 @@
+//! Whole games over the loopback transport: a host and several
+//! clients exchanging real messages, without a browser.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use robots::board::{Board, GenerateOptions};
+use robots::game::{Game, Phase, Recipient, HOST_ID};
+use robots::host::{Admission, Host};
+use robots::link::{self, Event, Link, Schedule};
+use robots::net::{self, Message, Metadata};
+use robots::rand::Rng;
+use robots::room::{ChatLine, Room};
+use robots::solver;
+use robots::transport::loopback::{LoopbackConnection, LoopbackPeer, Network};
+use robots::transport::{Connection, Transport};
+
+struct Client {
+    conn: LoopbackConnection,
+    room: Rc<RefCell<Room>>,
+}
+
+impl Client {
+    fn join(network: &Network, id: &str, name: &str) -> Client {
+        Client::connect(network.peer(id), "room", name, &format!("secret-{}", id))
+    }
+
+    fn connect(peer: LoopbackPeer, host: &str, name: &str, token: &str) -> Client {
+        let conn = peer.connect(host, &Metadata { name: name.into(), token: token.into(), ..Default::default() });
+        let room = Rc::new(RefCell::new(Room::new(&peer.id())));
+        let view = room.clone();
+        conn.on_data(move |message| view.borrow_mut().apply(&message, 0));
+        let hello = conn.clone();
+        conn.on_open(move || hello.send(&Message::Hello(net::HelloMessage::current())));
+        Client { conn, room }
+    }
+
+    fn send(&self, message: Message) {
+        self.conn.send(&message);
+    }
+}
+
+/// Callbacks waiting for their time to come.
+type Due = Vec<(u64, Box<dyn FnOnce()>)>;
+
+/// Timers for links, run by hand on the host's clock.
+struct Timers {
+    now: Rc<Cell<u64>>,
+    due: Rc<RefCell<Due>>,
+}
+
+impl Timers {
+    fn schedule(&self) -> Schedule {
+        let (now, due) = (self.now.clone(), self.due.clone());
+        Rc::new(move |delay, f| due.borrow_mut().push((now.get() + delay, f)))
+    }
+
+    /// Moves the clock on by `ms`, running timers as they
+    /// come due and delivering what they send.
+    fn advance(&self, network: &Network, ms: u64) {
+        let end = self.now.get() + ms;
+        loop {
+            network.run();
+            let next = self.due.borrow().iter().enumerate()
+                .filter(|(_, (at, _))| *at <= end)
+                .min_by_key(|(_, (at, _))| *at)
+                .map(|(i, _)| i);
+            let Some(next) = next else { break };
+            let (at, f) = self.due.borrow_mut().remove(next);
+            self.now.set(at.max(self.now.get()));
+            f();
+        }
+        self.now.set(end);
+    }
+}
+
+/// A client that stays in the game as hosts come and go,
+/// taking over itself if it's elected.
+struct Player {
+    link: Link<LoopbackPeer>,
+    room: Rc<RefCell<Room>>,
+    hosting: Rc<RefCell<Option<Host<LoopbackPeer>>>>,
+}
+
+impl Player {
+    fn join(network: &Network, timers: &Timers, id: &str, name: &str) -> Player {
+        let room = Rc::new(RefCell::new(Room::new(id)));
+        let hosting = Rc::new(RefCell::new(None));
+        let (view, host, me) = (room.clone(), hosting.clone(), id.to_string());
+        let metadata = Metadata { name: name.into(), token: format!("secret-{}", id), ..Default::default() };
+        let now = timers.now.clone();
+        let link = Link::connect(network.peer(id), "room", metadata, move || now.get(), timers.schedule(), move |event| match event {
+            Event::Welcomed => *view.borrow_mut() = Room::new(&me),
+            Event::Message(message) => view.borrow_mut().apply(&message, 0),
+            Event::Hosting(new_host) => {
+                *view.borrow_mut() = Room::new(HOST_ID);
+                *host.borrow_mut() = Some(new_host);
+            },
+            Event::Open | Event::Left(_) => {},
+        });
+        Player { link, room, hosting }
+    }
+}
+
+struct Setup {
+    network: Network,
+    clock: Rc<Cell<u64>>,
+    host: Host<LoopbackPeer>,
+    host_room: Rc<RefCell<Room>>,
+    clients: Vec<Client>,
+}
+
+fn setup(clients: usize) -> Setup {
+    let network = Network::new();
+    let clock = Rc::new(Cell::new(0));
+    let board = Board::generate(&GenerateOptions::default(), &mut Rng::new(7)).unwrap();
+
+    let host_room = Rc::new(RefCell::new(Room::new(HOST_ID)));
+    let view = host_room.clone();
+    let now = clock.clone();
+    let host = Host::new(network.peer("room"), Game::new(board, Rng::new(8)), move || now.get(),
+        move |message| view.borrow_mut().apply(&message, 0));
+    host.update(|game, _| game.join(HOST_ID, "Host"));
+
+    let clients = (0..clients)
+        .map(|i| Client::join(&network, &format!("client-{}", i), &format!("Player {}", i)))
+        .collect();
+    network.run();
+    Setup { network, clock, host, host_room, clients }
+}
+
+#[test]
+fn everyone_sees_everyone() {
+    let Setup { host: _host, host_room, clients, .. } = setup(3);
+    assert_eq!(host_room.borrow().players.len(), 4);
+    for client in &clients {
+        let room = client.room.borrow();
+        assert_eq!(room.players.len(), 4);
+        assert_eq!(room.players[HOST_ID], "Host");
+        assert_eq!(room.players["client-2"], "Player 2");
+    }
+}
+
+#[test]
+fn full_round() {
+    let Setup { network, clock, host, host_room, clients } = setup(3);
+
+    host.update(|game, _| game.start_round());
+    network.run();
+    let target = host.with_game(|game| game.target);
+    for client in &clients {
+        assert_eq!(client.room.borrow().phase, Phase::Revealed);
+        assert_eq!(client.room.borrow().target, target);
+    }
+
+    let (board, target) = host.with_game(|game| (game.board.clone(), game.board.targets[game.target.unwrap()]));
+    let solution = solver::solve(&board, board.initial_positions, &target, 10).expect("target is solvable");
+
+    // client 1 bids first, client 0 bids lower
+    clock.set(1_000);
+    clients[1].send(Message::MakeBid(net::MakeBidMessage { bid: solution.len() as u8 + 1, timestamp: Some(1_000) }));
+    network.run();
+    clock.set(2_000);
+    clients[0].send(Message::MakeBid(net::MakeBidMessage { bid: solution.len() as u8, timestamp: Some(2_000) }));
+    // bids of 0 are refused, and only the sender hears about it
+    clients[2].send(Message::MakeBid(net::MakeBidMessage { bid: 0, timestamp: None }));
+    network.run();
+
+    assert!(matches!(host_room.borrow().phase, Phase::Bidding { .. }));
+    assert_eq!(clients[2].room.borrow().bids.len(), 2);
+    assert!(clients[2].room.borrow().error.is_some());
+    assert!(clients[1].room.borrow().error.is_none());
+
+    clock.set(1_000 + robots::game::BID_DURATION);
+    host.tick();
+    network.run();
+    for client in &clients {
+        assert!(client.room.borrow().phase == Phase::Evaluating { player: "client-0".into(), bid: solution.len() as u8 });
+    }
+    assert!(clients[0].room.borrow().demonstrating());
+    assert!(clients[1].room.borrow().spectating());
+
+    clients[0].send(Message::SubmitMoves(net::SubmitMovesMessage { moves: solution }));
+    network.run();
+    for room in clients.iter().map(|client| &client.room).chain([&host_room]) {
+        let room = room.borrow();
+        assert!(room.verdict.as_ref().is_some_and(|verdict| verdict.success && verdict.player == "client-0"));
+        assert_eq!(room.scores["client-0"], 1);
+        // the next round has started
+        assert_eq!(room.phase, Phase::Revealed);
+    }
+}
+
+#[test]
+fn leaving_is_seen_by_everyone() {
+    let Setup { network, host, host_room, clients, .. } = setup(3);
+    clients[1].conn.close();
+    network.run();
+
+    assert!(!host.with_game(|game| game.players.contains_key("client-1")));
+    assert!(!host_room.borrow().players.contains_key("client-1"));
+    for i in [0, 2] {
+        assert_eq!(clients[i].room.borrow().players.len(), 3);
+    }
+}
+
+#[test]
+fn clients_must_say_hello() {
+    let Setup { network, host, clients, .. } = setup(1);
+    let seen = |conn: &LoopbackConnection| {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let view = log.clone();
+        conn.on_data(move |message| view.borrow_mut().push(message));
+        log
+    };
+
+    // a build from before the handshake
+    let stale = network.peer("stale").connect("room", &Metadata::default());
+    let stale_log = seen(&stale);
+    network.run();
+    stale.send(&Message::Ping(net::PingMessage { sent: 0 }));
+    // one too old for the host
+    let old = network.peer("old").connect("room", &Metadata::default());
+    let old_log = seen(&old);
+    network.run();
+    old.send(&Message::Hello(net::HelloMessage { version: 0, features: Vec::new() }));
+    // one that can't show demonstrations
+    let plain = network.peer("plain").connect("room", &Metadata::default());
+    let plain_log = seen(&plain);
+    network.run();
+    plain.send(&Message::Hello(net::HelloMessage { features: Vec::new(), ..net::HelloMessage::current() }));
+    network.run();
+
+    for log in [stale_log, old_log] {
+        assert!(matches!(&log.borrow()[..], [Message::Error(error)] if error.reason.contains("update")));
+    }
+    assert_eq!(host.with_game(|game| game.players.len()), 3);
+
+    assert!(matches!(&plain_log.borrow()[0], Message::Welcome(welcome) if welcome.features.is_empty()));
+
+    let full_log = seen(&clients[0].conn);
+    plain_log.borrow_mut().clear();
+    let demo = Message::DemoReset(net::DemoResetMessage { player: HOST_ID.into() });
+    host.update(|_, _| vec![(Recipient::All, demo)]);
+    network.run();
+    assert!(plain_log.borrow().is_empty());
+    assert_eq!(full_log.borrow().len(), 1);
+}
+
+#[test]
+fn spectators_follow_along() {
+    let Setup { network, host, host_room, clients, .. } = setup(1);
+    host.update(|game, _| game.start_round());
+    let conn = network.peer("watcher").connect("room", &Metadata { name: "Watcher".into(), spectator: true, ..Default::default() });
+    let room = Rc::new(RefCell::new(Room::new("watcher")));
+    let view = room.clone();
+    conn.on_data(move |message| view.borrow_mut().apply(&message, 0));
+    network.run();
+    conn.send(&Message::Hello(net::HelloMessage::current()));
+    network.run();
+
+    clients[0].send(Message::MakeBid(net::MakeBidMessage { bid: 5, timestamp: None }));
+    conn.send(&Message::MakeBid(net::MakeBidMessage { bid: 4, timestamp: None }));
+    network.run();
+
+    let room = room.borrow();
+    assert_eq!(room.target, host.with_game(|game| game.target));
+    assert_eq!(room.bids.len(), 1);
+    assert!(room.error.is_some());
+    for players in [&room.players, &host_room.borrow().players, &clients[0].room.borrow().players] {
+        assert_eq!(players.len(), 2);
+        assert!(!players.contains_key("watcher"));
+    }
+}
+
+#[test]
+fn hosts_choose_who_joins() {
+    let Setup { network, clock, host, clients, .. } = setup(1);
+    host.set_admission(Admission { password: Some("sesame".into()), approve: true });
+    network.run();
+    // players already in know, to keep approving if they take over
+    assert!(clients[0].room.borrow().welcome.as_ref().is_some_and(|welcome| welcome.approve));
+    let waiting = Rc::new(RefCell::new(Vec::new()));
+    let view = waiting.clone();
+    host.on_pending(move |pending| *view.borrow_mut() = pending.into_iter().map(|(id, _)| id).collect());
+
+    let knock = |id: &str, password: &str| {
+        let conn = network.peer(id).connect("room", &Metadata { name: id.into(), password: password.into(), ..Default::default() });
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let view = log.clone();
+        conn.on_data(move |message| view.borrow_mut().push(message));
+        let closed = Rc::new(Cell::new(false));
+        let view = closed.clone();
+        conn.on_close(move || view.set(true));
+        network.run();
+        conn.send(&Message::Hello(net::HelloMessage::current()));
+        network.run();
+        (log, closed)
+    };
+    let refused = |log: &RefCell<Vec<Message>>, why: &str| {
+        matches!(&log.borrow()[..], [.., Message::Error(error)] if error.reason.contains(why))
+    };
+
+    let (guesser, guesser_closed) = knock("guesser", "open");
+    assert!(refused(&guesser, "password"));
+
+    let (friend, friend_closed) = knock("friend", "sesame");
+    let (stranger, stranger_closed) = knock("stranger", "sesame");
+    // those waiting know the host is there
+    assert!(matches!(&friend.borrow()[..], [Message::Pending(_)]));
+    assert_eq!(*waiting.borrow(), ["friend", "stranger"]);
+
+    host.admit("friend");
+    host.reject("stranger", "Not tonight.");
+    network.run();
+    assert!(waiting.borrow().is_empty());
+    assert!(matches!(&friend.borrow()[1], Message::Welcome(welcome) if welcome.approve));
+    assert!(refused(&stranger, "Not tonight."));
+    assert_eq!(clients[0].room.borrow().players["friend"], "friend");
+    assert!(!host.with_game(|game| game.players.contains_key("stranger")));
+
+    // the turned away get a moment to read why, then are hung up on
+    assert!(!guesser_closed.get());
+    clock.set(robots::host::CLOSE_GRACE);
+    host.tick();
+    network.run();
+    assert!(guesser_closed.get() && stranger_closed.get());
+    assert!(!friend_closed.get());
+}
+
+#[test]
+fn reconnecting_reclaims_the_score() {
+    let Setup { network, clock, host, clients, .. } = setup(2);
+    host.update(|game, _| game.start_round());
+    clients[0].send(Message::MakeBid(net::MakeBidMessage { bid: 9, timestamp: None }));
+    network.run();
+    clock.set(robots::game::BID_DURATION);
+    host.tick();
+    host.update(|game, _| game.resolve(true));
+    network.run();
+    assert_eq!(clients[1].room.borrow().scores["client-0"], 1);
+
+    // back under a new peer ID, with the old token
+    clients[0].conn.close();
+    network.run();
+    assert!(!clients[1].room.borrow().players.contains_key("client-0"));
+    let back = Client::connect(network.peer("client-0b"), "room", "Player 0", "secret-client-0");
+    network.run();
+    for room in [&back.room, &clients[1].room] {
+        let room = room.borrow();
+        assert_eq!(room.scores["client-0b"], 1);
+        assert_eq!(room.target, host.with_game(|game| game.target));
+    }
+    assert_eq!(back.room.borrow().players.len(), 3);
+
+    // the score is gone once the grace period is over
+    back.conn.close();
+    network.run();
+    clock.set(robots::game::BID_DURATION + robots::game::REJOIN_GRACE);
+    host.tick();
+    let late = Client::connect(network.peer("client-0c"), "room", "Player 0", "secret-client-0");
+    network.run();
+    assert_eq!(late.room.borrow().scores["client-0c"], 0);
+}
+
+#[test]
+fn new_connections_replace_old_ones() {
+    let Setup { network, clock, host, host_room, clients } = setup(2);
+    host.update(|game, _| game.start_round());
+    clients[0].send(Message::MakeBid(net::MakeBidMessage { bid: 9, timestamp: None }));
+    network.run();
+    clock.set(robots::game::BID_DURATION);
+    host.tick();
+    host.update(|game, _| game.resolve(true));
+    network.run();
+    let closed = |conn: &LoopbackConnection| {
+        let closed = Rc::new(Cell::new(false));
+        let view = closed.clone();
+        conn.on_close(move || view.set(true));
+        closed
+    };
+    let old_closed = closed(&clients[0].conn);
+
+    // back as the same peer, before the host hears the old connection close
+    let again = Client::connect(network.peer("client-0"), "room", "Player 0", "secret-client-0");
+    network.run();
+    assert!(old_closed.get());
+    assert!(again.room.borrow().welcome.is_some());
+    assert!(host.with_game(|game| game.players.contains_key("client-0")));
+    for room in [&again.room, &clients[1].room] {
+        assert_eq!(room.borrow().scores["client-0"], 1);
+    }
+    assert!(clients[1].room.borrow().chat.iter().all(|line| !line.text.contains("left")));
+    again.send(Message::Chat(net::ChatMessage { text: "back".into(), ..Default::default() }));
+    network.run();
+    assert_eq!(clients[1].room.borrow().chat.back().map(|line| line.text.as_str()), Some("back"));
+
+    // as a new peer with the same token, they take over their old place
+    let again_closed = closed(&again.conn);
+    let moved = Client::connect(network.peer("client-0b"), "room", "Player 0", "secret-client-0");
+    network.run();
+    assert!(again_closed.get());
+    for room in [&moved.room, &clients[1].room, &host_room] {
+        let room = room.borrow();
+        assert!(!room.players.contains_key("client-0"));
+        assert_eq!(room.scores["client-0b"], 1);
+    }
+}
+
+#[test]
+fn hosts_kick_and_ban() {
+    let Setup { network, clock, host, host_room, clients, .. } = setup(2);
+    let closed = Rc::new(Cell::new(false));
+    let view = closed.clone();
+    clients[0].conn.on_close(move || view.set(true));
+
+    host.kick("client-0", "Behave.");
+    network.run();
+    assert_eq!(clients[0].room.borrow().error.as_deref(), Some("Behave."));
+    assert!(!clients[1].room.borrow().players.contains_key("client-0"));
+    assert!(!host_room.borrow().players.contains_key("client-0"));
+
+    // the host stops listening, and hangs up once they've read why
+    clients[0].send(Message::Chat(net::ChatMessage { text: "but".into(), ..Default::default() }));
+    network.run();
+    assert!(clients[1].room.borrow().chat.iter().all(|line| line.name.is_none()));
+    assert!(!closed.get());
+    clock.set(robots::host::CLOSE_GRACE);
+    host.tick();
+    network.run();
+    assert!(closed.get());
+
+    // a kicked player may come back
+    let back = Client::connect(network.peer("client-0b"), "room", "Player 0", "secret-client-0");
+    network.run();
+    assert!(back.room.borrow().welcome.is_some());
+
+    // a banned one may not, even as a new peer
+    host.ban("client-0b", "Goodbye.");
+    network.run();
+    assert_eq!(back.room.borrow().error.as_deref(), Some("Goodbye."));
+    let again = Client::connect(network.peer("client-0c"), "room", "Player 0", "secret-client-0");
+    network.run();
+    assert!(again.room.borrow().error.as_deref().is_some_and(|error| error.contains("banned")));
+    assert!(!host.with_game(|game| game.players.contains_key("client-0c")));
+}
+
+#[test]
+fn players_chat_through_the_host() {
+    let Setup { network, clock, host, host_room, clients, .. } = setup(2);
+    clock.set(5);
+    clients[0].send(Message::Chat(net::ChatMessage { text: "good luck".into(), ..Default::default() }));
+    network.run();
+    for room in [&host_room, &clients[1].room] {
+        let line = room.borrow().chat.back().cloned().unwrap();
+        assert_eq!(line, ChatLine { time: 5, name: Some("Player 0".into()), text: "good luck".into() });
+    }
+
+    // everyone hears about comings and goings, but not about
+    // the players already there when they joined
+    clients[0].conn.close();
+    network.run();
+    let late = Client::join(&network, "late", "Late");
+    network.run();
+    let notices = |room: &RefCell<Room>| room.borrow().chat.iter()
+        .filter(|line| line.name.is_none())
+        .map(|line| line.text.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(notices(&clients[1].room), ["Player 0 left.", "Late joined."]);
+    assert!(notices(&late.room).is_empty());
+
+    host.update(|game, _| game.start_round());
+    late.send(Message::MakeBid(net::MakeBidMessage { bid: 5, timestamp: None }));
+    network.run();
+    assert_eq!(notices(&late.room), ["Bidding is open."]);
+}
+
+#[test]
+fn clients_measure_the_host_clock() {
+    let Setup { network, clock, host: _host, clients, .. } = setup(1);
+    let pong = Rc::new(Cell::new(None));
+    let seen = pong.clone();
+    clients[0].conn.on_data(move |message| {
+        if let Message::Pong(msg) = message {
+            seen.set(Some(msg.host_time));
+        }
+    });
+
+    clock.set(42_000);
+    clients[0].send(Message::Ping(net::PingMessage { sent: 7 }));
+    network.run();
+    assert_eq!(pong.get(), Some(42_000));
+}
+
+#[test]
+fn clients_take_over_when_the_host_leaves() {
+    let Setup { network, clock, host, .. } = setup(0);
+    let timers = Timers { now: clock.clone(), due: Default::default() };
+    let players: Vec<Player> = (0..3)
+        .map(|i| Player::join(&network, &timers, &format!("client-{}", i), &format!("Player {}", i)))
+        .collect();
+    host.update(|game, _| game.start_round());
+    network.run();
+
+    // client 1 wins a round, then client 2 bids in the next
+    players[1].link.send(&Message::MakeBid(net::MakeBidMessage { bid: 9, timestamp: None }));
+    network.run();
+    clock.set(robots::game::BID_DURATION);
+    host.tick();
+    host.update(|game, _| game.resolve(true));
+    clock.set(robots::game::BID_DURATION + 1_000);
+    players[2].link.send(&Message::MakeBid(net::MakeBidMessage { bid: 7, timestamp: None }));
+    network.run();
+    let target = host.with_game(|game| game.target);
+
+    // client 0, who has the lowest ID, is slow to hear the host
+    // has gone, so the others reach it before it's hosting
+    network.hold("client-0");
+    host.destroy();
+    drop(host);
+    timers.advance(&network, link::CONNECT_TIMEOUT);
+    assert!(players[0].hosting.borrow().is_none());
+    for player in &players[1..] {
+        assert_eq!(player.link.host(), "client-0");
+    }
+
+    // they keep trying it until it takes over
+    network.release("client-0");
+    timers.advance(&network, link::CONNECT_TIMEOUT);
+    assert!(players[0].hosting.borrow().is_some());
+    for player in &players {
+        let room = player.room.borrow();
+        assert_eq!(room.players.len(), 3);
+        assert_eq!(room.players[HOST_ID], "Player 0");
+        assert_eq!(room.scores["client-1"], 1);
+        assert_eq!(room.target, target);
+        assert!(matches!(room.phase, Phase::Bidding { .. }));
+        assert_eq!(room.bids["client-2"].bid, 7);
+    }
+}
+
+#[test]
+fn clients_elect_again_when_the_successor_stays_quiet() {
+    let Setup { network, clock, host, .. } = setup(0);
+    let timers = Timers { now: clock.clone(), due: Default::default() };
+    let players: Vec<Player> = (0..3)
+        .map(|i| Player::join(&network, &timers, &format!("client-{}", i), &format!("Player {}", i)))
+        .collect();
+    network.run();
+
+    // client 0 never hears the host has gone
+    network.hold("client-0");
+    host.destroy();
+    drop(host);
+    timers.advance(&network, link::MIGRATION_DELAY * 2 + (link::WELCOME_TIMEOUT + link::MIGRATION_DELAY) * link::SILENT_TRIES as u64);
+    assert!(players[1].hosting.borrow().is_some());
+    assert_eq!(players[2].link.host(), "client-1");
+    assert_eq!(players[2].room.borrow().players[HOST_ID], "Player 1");
+}
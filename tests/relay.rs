@@ -0,0 +1,108 @@
+//! The relay server, driven by plain WebSocket clients.
+
+use std::cell::RefCell;
+use std::net::{TcpListener, TcpStream};
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use robots::board::{Board, GenerateOptions};
+use robots::game::Game;
+use robots::host::Host;
+use robots::net::{HelloMessage, Message, Metadata, PingMessage};
+use robots::rand::Rng;
+use robots::relay::client::Client;
+use robots::relay::{self, Frame};
+use robots::room::Room;
+use robots::transport::{Connection, Transport};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::WebSocket;
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+fn start() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("ws://{}", listener.local_addr().unwrap());
+    thread::spawn(move || relay::server::serve(listener));
+    address
+}
+
+fn send(socket: &mut Socket, frame: &Frame) {
+    socket.send(tungstenite::Message::Text(serde_json::to_string(frame).unwrap())).unwrap();
+}
+
+fn receive(socket: &mut Socket) -> Frame {
+    loop {
+        if let tungstenite::Message::Text(text) = socket.read().unwrap() {
+            return serde_json::from_str(&text).unwrap();
+        }
+    }
+}
+
+fn join(address: &str, room: &str, peer: &str) -> Socket {
+    let (mut socket, _) = tungstenite::connect(address).unwrap();
+    send(&mut socket, &Frame::Hello { room: room.into(), peer: peer.into() });
+    assert_eq!(receive(&mut socket), Frame::Welcome { peer: peer.into() });
+    socket
+}
+
+#[test]
+fn relays_messages_within_a_room() {
+    let address = start();
+    let mut host = join(&address, "room", "host");
+    let mut alice = join(&address, "room", "alice");
+
+    let metadata = Metadata { name: "Alice".into(), ..Default::default() };
+    send(&mut alice, &Frame::Connect { peer: "host".into(), metadata: metadata.clone() });
+    assert_eq!(receive(&mut host), Frame::Open { peer: "alice".into(), metadata: metadata.clone() });
+    assert_eq!(receive(&mut alice), Frame::Open { peer: "host".into(), metadata });
+
+    let ping = serde_json::to_value(Message::Ping(PingMessage { sent: 5 })).unwrap();
+    send(&mut alice, &Frame::Data { peer: "host".into(), message: ping.clone() });
+    assert_eq!(receive(&mut host), Frame::Data { peer: "alice".into(), message: ping });
+
+    // the other end hears when a socket goes away
+    alice.close(None).unwrap();
+    assert_eq!(receive(&mut host), Frame::Close { peer: "alice".into() });
+}
+
+#[test]
+fn rooms_do_not_mix() {
+    let address = start();
+    let _host = join(&address, "one", "host");
+    let mut bob = join(&address, "two", "bob");
+
+    send(&mut bob, &Frame::Connect { peer: "host".into(), metadata: Metadata::default() });
+    assert_eq!(receive(&mut bob), Frame::Close { peer: "host".into() });
+
+    // malformed frames are answered, not dropped
+    bob.send(tungstenite::Message::Text("{\"frame\":\"nonsense\"}".into())).unwrap();
+    assert!(matches!(receive(&mut bob), Frame::Error { .. }));
+}
+
+#[test]
+fn hosts_games_over_the_relay() {
+    let address = start();
+    let board = Board::generate(&GenerateOptions::default(), &mut Rng::new(1)).unwrap();
+    let host_client = Client::connect(&address, "room", "host").unwrap();
+    let _host = Host::new(host_client.peer().clone(), Game::new(board, Rng::new(2)), || 0, |_| {});
+
+    let alice = Client::connect(&address, "room", "alice").unwrap();
+    let conn = alice.peer().connect("host", &Metadata { name: "Alice".into(), ..Default::default() });
+    let room = Rc::new(RefCell::new(Room::new("alice")));
+    let state = room.clone();
+    conn.on_data(move |message| state.borrow_mut().apply(&message, 0));
+    let hello = conn.clone();
+    conn.on_open(move || hello.send(&Message::Hello(HelloMessage::current())));
+
+    // both ends are driven from this thread
+    let poll = Duration::from_millis(10);
+    for _ in 0..200 {
+        host_client.poll(poll).unwrap();
+        alice.poll(poll).unwrap();
+        if room.borrow().players.contains_key("alice") {
+            break;
+        }
+    }
+    assert_eq!(room.borrow().players["alice"], "Alice");
+}